@@ -0,0 +1,102 @@
+//! Compares finding a literal line by line, as `search` does, with the fast
+//! path that finds it across the whole buffer, on several MB of text.
+
+use std::env;
+use std::fs;
+use std::hint::black_box;
+use std::io;
+use std::path::Path;
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use minigrep::{Match, Searcher, Sink};
+
+const QUERY: &str = "needle";
+
+/// Counts the selected lines and nothing else.
+struct Count(usize);
+
+impl Sink for Count {
+    fn matched(&mut self, _m: &Match) -> io::Result<bool> {
+        self.0 += 1;
+        Ok(true)
+    }
+}
+
+/// About 8 MB of lines of made-up words, one in a thousand with `QUERY` in it.
+fn corpus() -> String {
+    const WORDS: [&str; 8] = [
+        "haystack", "straw", "barn", "hay", "field", "bale", "nettle", "nee",
+    ];
+    let mut state = 0x2545_f491_u32;
+    let mut next = move || {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        state as usize
+    };
+
+    let mut text = String::new();
+    for line in 0..200_000 {
+        for _ in 0..6 {
+            text.push_str(WORDS[next() % WORDS.len()]);
+            text.push(' ');
+        }
+        if line % 1000 == 0 {
+            text.push_str(QUERY);
+        }
+        text.push('\n');
+    }
+    text
+}
+
+fn in_memory(c: &mut Criterion) {
+    let text = corpus();
+    let mut group = c.benchmark_group("in_memory");
+    group.throughput(Throughput::Bytes(text.len() as u64));
+
+    group.bench_function("lines", |b| {
+        b.iter(|| minigrep::search(black_box(QUERY), black_box(&text)).len())
+    });
+    group.bench_function("memmem", |b| {
+        b.iter(|| {
+            let mut count = Count(0);
+            let searcher = Searcher::default();
+            searcher
+                .search_slice(black_box(QUERY), black_box(text.as_bytes()), &mut count)
+                .unwrap();
+            count.0
+        })
+    });
+    group.finish();
+}
+
+fn file(c: &mut Criterion) {
+    let text = corpus();
+    let path = env::temp_dir().join(format!("minigrep-bench-{}", std::process::id()));
+    fs::write(&path, &text).unwrap();
+    let path = Path::new(&path);
+
+    let mut group = c.benchmark_group("file");
+    group.throughput(Throughput::Bytes(text.len() as u64));
+    group.bench_function("read_lines", |b| {
+        b.iter(|| {
+            let contents = fs::read_to_string(path).unwrap();
+            minigrep::search(black_box(QUERY), &contents).len()
+        })
+    });
+    group.bench_function("mmap_memmem", |b| {
+        b.iter(|| {
+            let mut count = Count(0);
+            Searcher::default()
+                .search_path(black_box(QUERY), path, &mut count)
+                .unwrap();
+            count.0
+        })
+    });
+    group.finish();
+
+    fs::remove_file(path).unwrap();
+}
+
+criterion_group!(benches, in_memory, file);
+criterion_main!(benches);
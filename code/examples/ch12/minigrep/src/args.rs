@@ -0,0 +1,329 @@
+use std::error::Error;
+use std::fmt;
+
+pub const USAGE: &str = "\
+Usage: minigrep [OPTIONS] <QUERY> <PATH>...
+       minigrep [OPTIONS] -e <QUERY>... <PATH>...
+       minigrep [OPTIONS] -f <FILE> <PATH>...
+       minigrep [OPTIONS] --interactive <PATH>...
+       minigrep --build-index <DIR>...
+
+Search for QUERY in each PATH. A PATH of - reads standard input.
+Default flags, colors and ignore rules are read from MINIGREP_CONFIG or
+~/.config/minigrep/config.toml; flags given here take precedence, and
+on/off flags it sets are turned off again with --no-NAME, e.g. --no-hidden
+or --no-ignore-case (also for -E -w -x -r -n -b -v -a -z -U).
+With -r, hidden, ignored and binary files are skipped unless named.
+--build-index keeps the trigrams of each file under DIR in
+DIR/.minigrep-index so searches of DIR or anything in it can skip files
+that can't match; run again, it only rereads changed files. Files changed
+since are searched as usual, though an edit that keeps a file's length
+and comes within one tick of the file system's clock of indexing it can
+go unseen.
+The exit status is 0 if a line was selected, 1 if none was and 2 on error.
+
+Options:
+  -i, --ignore-case             Match without regard to case (also set by IGNORE_CASE)
+  -E, --regex                   Treat QUERY as a regular expression
+  -e, --regexp <QUERY>          Search for QUERY; repeat to search for several
+  -f, --file <FILE>             Search for every line of FILE
+  -w, --word-regexp             Only match QUERY as a whole word
+  -x, --line-regexp             Only match QUERY as the entire line
+      --fuzzy <K>               Also match text up to K edits away from QUERY,
+                                marking each line with its distance as ~D;
+                                not with -E, -w or -x
+  -U, --multiline               Let matches span lines, printing every line one covers
+  -r, --recursive               Search directories recursively
+      --hidden                  Also search hidden files and directories
+      --no-ignore               Don't skip files ignored by .gitignore, .ignore or config
+  -a, --text                    Search binary files as if they were text
+  -z, --search-zip              Search inside gzip, zstd and bzip2 files
+      --encoding <LABEL>        Decode input from LABEL, e.g. utf-16le or latin1
+      --include <GLOB>          Only search files whose name matches GLOB
+      --exclude <GLOB>          Skip files whose name matches GLOB
+  -n, --line-number             Prefix each line with its line number
+  -b, --byte-offset             Prefix each line with its byte offset in the input
+  -A, --after-context <NUM>     Print NUM lines after each match
+  -B, --before-context <NUM>    Print NUM lines before each match
+  -C, --context <NUM>           Print NUM lines before and after each match
+  -v, --invert-match            Select the lines that don't match
+  -m, --max-count <NUM>         Stop reading a file after NUM selected lines
+  -c, --count                   Print only the number of selected lines per file
+  -l, --files-with-matches      Print only the names of files with selected lines
+  -L, --files-without-match     Print only the names of files without selected lines
+  -q, --quiet, --silent         Print nothing, only set the exit status
+      --json                    Print results as JSON Lines, one event per line
+      --replace <TEXT>          Print selected lines with each match replaced by TEXT
+                                ($1, ${name} refer to capture groups with -E)
+      --in-place                Write the replacements back to the files
+      --backup                  With --in-place, keep the original as FILE.bak
+  -F, --follow                  Keep printing matches as PATH grows, like tail -F
+  -j, --threads <NUM>           Search NUM files at once (default: one per CPU)
+      --interactive             Search as you type, then print the chosen file:line
+      --build-index <DIR>       Build or update the index of DIR instead of searching;
+                                repeat to index several
+      --color[=WHEN]            Highlight matches: auto (default), always or never
+      --no-config               Don't read the config file
+  -h, --help                    Print this help and exit
+  -V, --version                 Print version information and exit
+      --                        Treat every following argument as QUERY or PATH";
+
+/// Everything that can go wrong while reading the command line.
+///
+/// `Help` and `Version` are not failures as such, but like every other
+/// variant they stop parsing and leave it to the caller to print the message.
+#[derive(Debug, PartialEq, Eq)]
+pub enum ArgsError {
+    MissingQuery,
+    MissingFilePath,
+    UnknownOption(String),
+    PatternFile { path: String, reason: String },
+    ConfigFile { path: String, reason: String },
+    MissingValue(String),
+    UnexpectedValue { option: String, value: String },
+    InvalidValue { option: String, value: String },
+    Requires { option: String, needs: String },
+    Conflict { option: String, with: String },
+    Help,
+    Version,
+}
+
+impl ArgsError {
+    /// Whether this is a `--help`/`--version` request rather than a mistake.
+    pub fn is_informational(&self) -> bool {
+        matches!(self, Self::Help | Self::Version)
+    }
+}
+
+impl fmt::Display for ArgsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingQuery => write!(f, "Didn't get a query string"),
+            Self::MissingFilePath => write!(f, "Didn't get a file path"),
+            Self::UnknownOption(option) => write!(f, "Unknown option '{option}'"),
+            Self::PatternFile { path, reason } => {
+                write!(f, "Couldn't read patterns from {path}: {reason}")
+            }
+            Self::ConfigFile { path, reason } => {
+                write!(f, "Couldn't read config file {path}: {reason}")
+            }
+            Self::MissingValue(option) => write!(f, "Option '{option}' needs a value"),
+            Self::UnexpectedValue { option, value } => {
+                write!(f, "Option '{option}' doesn't take a value (got '{value}')")
+            }
+            Self::InvalidValue { option, value } => {
+                write!(f, "Invalid value '{value}' for option '{option}'")
+            }
+            Self::Requires { option, needs } => {
+                write!(f, "Option '{option}' only works with '{needs}'")
+            }
+            Self::Conflict { option, with } => {
+                write!(f, "Option '{option}' can't be used with '{with}'")
+            }
+            Self::Help => write!(f, "{USAGE}"),
+            Self::Version => write!(f, "minigrep {}", env!("CARGO_PKG_VERSION")),
+        }
+    }
+}
+
+impl Error for ArgsError {}
+
+/// A single command-line item, as split up by [`Parser`].
+#[derive(Debug, PartialEq, Eq)]
+pub enum Arg {
+    Short(char),
+    Long(String),
+    Positional(String),
+}
+
+impl Arg {
+    /// The error to give back when this argument isn't one we know.
+    pub fn unexpected(self) -> ArgsError {
+        match self {
+            Self::Short(c) => ArgsError::UnknownOption(format!("-{c}")),
+            Self::Long(name) => ArgsError::UnknownOption(format!("--{name}")),
+            Self::Positional(value) => ArgsError::UnknownOption(value),
+        }
+    }
+}
+
+/// Splits raw arguments into options and positionals.
+///
+/// Options can appear anywhere, short flags can be combined (`-ir`), values
+/// can be attached (`-A3`, `--context=3`) or separate (`-A 3`), and `--`
+/// turns every later argument into a positional.
+pub struct Parser<I: Iterator<Item = String>> {
+    args: I,
+    shorts: Vec<char>,
+    long_value: Option<String>,
+    last_option: String,
+    options_done: bool,
+}
+
+impl<I: Iterator<Item = String>> Parser<I> {
+    pub fn new(args: I) -> Self {
+        Self {
+            args,
+            shorts: Vec::new(),
+            long_value: None,
+            last_option: String::new(),
+            options_done: false,
+        }
+    }
+
+    pub fn next_arg(&mut self) -> Result<Option<Arg>, ArgsError> {
+        if let Some(value) = self.long_value.take() {
+            return Err(ArgsError::UnexpectedValue {
+                option: self.last_option.clone(),
+                value,
+            });
+        }
+
+        if !self.shorts.is_empty() {
+            let c = self.shorts.remove(0);
+            self.last_option = format!("-{c}");
+            return Ok(Some(Arg::Short(c)));
+        }
+
+        let Some(arg) = self.args.next() else {
+            return Ok(None);
+        };
+
+        if self.options_done || arg == "-" || !arg.starts_with('-') {
+            return Ok(Some(Arg::Positional(arg)));
+        }
+
+        if arg == "--" {
+            self.options_done = true;
+            return self.next_arg();
+        }
+
+        if let Some(long) = arg.strip_prefix("--") {
+            let name = match long.split_once('=') {
+                Some((name, value)) => {
+                    self.long_value = Some(value.to_string());
+                    name
+                }
+                None => long,
+            };
+            self.last_option = format!("--{name}");
+            return Ok(Some(Arg::Long(name.to_string())));
+        }
+
+        self.shorts = arg.chars().skip(1).collect();
+        self.next_arg()
+    }
+
+    /// Takes the value for the option that was just returned by `next_arg`.
+    pub fn value(&mut self) -> Result<String, ArgsError> {
+        if let Some(value) = self.long_value.take() {
+            return Ok(value);
+        }
+
+        if !self.shorts.is_empty() {
+            return Ok(self.shorts.drain(..).collect());
+        }
+
+        self.args
+            .next()
+            .ok_or_else(|| ArgsError::MissingValue(self.last_option.clone()))
+    }
+
+    /// Whether the last option came with `=value` attached, for options whose
+    /// value is optional and so can't take the next argument as theirs.
+    pub fn has_attached_value(&self) -> bool {
+        self.long_value.is_some()
+    }
+
+    /// Takes the value for the last option and parses it, e.g. as a number.
+    pub fn parse_value<T: std::str::FromStr>(&mut self) -> Result<T, ArgsError> {
+        let value = self.value()?;
+        value.parse().map_err(|_| ArgsError::InvalidValue {
+            option: self.last_option.clone(),
+            value,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Vec<Result<Option<Arg>, ArgsError>> {
+        let mut parser = Parser::new(args.iter().map(|a| a.to_string()));
+        let mut out = Vec::new();
+        loop {
+            let arg = parser.next_arg();
+            let done = !matches!(arg, Ok(Some(_)));
+            out.push(arg);
+            if done {
+                return out;
+            }
+        }
+    }
+
+    #[test]
+    fn combined_shorts_and_positionals() {
+        assert_eq!(
+            vec![
+                Ok(Some(Arg::Short('i'))),
+                Ok(Some(Arg::Short('r'))),
+                Ok(Some(Arg::Positional("to".to_string()))),
+                Ok(Some(Arg::Positional("-".to_string()))),
+                Ok(None),
+            ],
+            parse(&["-ir", "to", "-"])
+        );
+    }
+
+    #[test]
+    fn double_dash_ends_options() {
+        assert_eq!(
+            vec![
+                Ok(Some(Arg::Positional("-i".to_string()))),
+                Ok(Some(Arg::Positional("--".to_string()))),
+                Ok(None),
+            ],
+            parse(&["--", "-i", "--"])
+        );
+    }
+
+    #[test]
+    fn values_attached_or_separate() {
+        let args = ["-A5", "-A", "6", "--context=7", "--context", "8"];
+        let mut parser = Parser::new(args.iter().map(|a| a.to_string()));
+        for expected in 5..=8 {
+            assert!(parser.next_arg().unwrap().is_some());
+            assert_eq!(Ok(expected), parser.parse_value::<usize>());
+        }
+        assert_eq!(Ok(None), parser.next_arg());
+    }
+
+    #[test]
+    fn value_errors() {
+        let mut parser = Parser::new(["-A".to_string()].into_iter());
+        parser.next_arg().unwrap();
+        assert_eq!(
+            Err(ArgsError::MissingValue("-A".to_string())),
+            parser.value()
+        );
+
+        let mut parser = Parser::new(["--context=x".to_string()].into_iter());
+        parser.next_arg().unwrap();
+        assert!(matches!(
+            parser.parse_value::<usize>(),
+            Err(ArgsError::InvalidValue { .. })
+        ));
+    }
+
+    #[test]
+    fn flags_do_not_take_values() {
+        // A flag that takes no value must not swallow one
+        let mut parser = Parser::new(["--regex=yes".to_string()].into_iter());
+        parser.next_arg().unwrap();
+        assert!(matches!(
+            parser.next_arg(),
+            Err(ArgsError::UnexpectedValue { .. })
+        ));
+    }
+}
@@ -0,0 +1,131 @@
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
+
+use bzip2::read::MultiBzDecoder;
+use flate2::read::MultiGzDecoder;
+
+/// The compression formats `-z` can see through.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    Gzip,
+    Zstd,
+    Bzip2,
+}
+
+impl Compression {
+    /// Works out how a file is compressed from its first bytes, or failing
+    /// that from its extension. `None` means it's read as it is.
+    pub fn detect(path: &Path, start: &[u8]) -> Option<Self> {
+        if let Some(compression) = Self::from_magic(start) {
+            return Some(compression);
+        }
+
+        match path.extension()?.to_str()? {
+            "gz" => Some(Self::Gzip),
+            "zst" => Some(Self::Zstd),
+            "bz2" => Some(Self::Bzip2),
+            _ => None,
+        }
+    }
+
+    /// The format whose magic bytes `start` begins with, if any.
+    fn from_magic(start: &[u8]) -> Option<Self> {
+        if start.starts_with(&[0x1f, 0x8b]) {
+            return Some(Self::Gzip);
+        }
+        if start.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            return Some(Self::Zstd);
+        }
+        // "BZh" alone starts plenty of text, so also look for the block size
+        // and then the magic of the first block, or of the end of an empty stream
+        if let [b'B', b'Z', b'h', b'1'..=b'9', block @ ..] = start {
+            if block.starts_with(b"1AY&SY") || block.starts_with(b"\x17rE8P\x90") {
+                return Some(Self::Bzip2);
+            }
+        }
+        None
+    }
+
+    /// Wraps `reader` so that reading from it gives the decompressed bytes.
+    /// Files of several concatenated streams, as `cat a.gz b.gz` makes, are
+    /// read to the end.
+    pub fn decoder<'a>(self, reader: impl BufRead + 'a) -> io::Result<Box<dyn BufRead + 'a>> {
+        Ok(match self {
+            Self::Gzip => Box::new(BufReader::new(MultiGzDecoder::new(reader))),
+            Self::Zstd => Box::new(BufReader::new(zstd::Decoder::with_buffer(reader)?)),
+            Self::Bzip2 => Box::new(BufReader::new(MultiBzDecoder::new(reader))),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::{Read, Write};
+
+    use flate2::write::GzEncoder;
+
+    fn decompress(compression: Compression, compressed: &[u8]) -> String {
+        let mut decompressed = String::new();
+        compression
+            .decoder(compressed)
+            .unwrap()
+            .read_to_string(&mut decompressed)
+            .unwrap();
+        decompressed
+    }
+
+    #[test]
+    fn detects_by_magic_bytes_then_extension() {
+        let plain = Path::new("notes.txt");
+        assert_eq!(
+            Some(Compression::Gzip),
+            Compression::detect(plain, b"\x1f\x8b\x08")
+        );
+        assert_eq!(
+            Some(Compression::Zstd),
+            Compression::detect(plain, b"\x28\xb5\x2f\xfd")
+        );
+        assert_eq!(
+            Some(Compression::Bzip2),
+            Compression::detect(plain, b"BZh91AY&SY")
+        );
+        assert_eq!(
+            Some(Compression::Bzip2),
+            Compression::detect(plain, b"BZh9\x17rE8P\x90")
+        );
+        assert_eq!(None, Compression::detect(plain, b"plain text"));
+        // Text that only happens to start like bzip2 isn't taken for it
+        assert_eq!(None, Compression::detect(plain, b"BZh is a magic number"));
+        assert_eq!(None, Compression::detect(plain, b"BZh0 1AY&SY"));
+        assert_eq!(
+            Some(Compression::Bzip2),
+            Compression::detect(Path::new("app.log.bz2"), b"")
+        );
+    }
+
+    #[test]
+    fn reads_every_format() {
+        let text = "first line\nsecond line\n";
+
+        let mut gzip = GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gzip.write_all(text.as_bytes()).unwrap();
+        let gzip = gzip.finish().unwrap();
+        // Two streams back to back read as one file
+        assert_eq!(
+            text.repeat(2),
+            decompress(Compression::Gzip, &[&gzip[..], &gzip[..]].concat())
+        );
+
+        let zstd = zstd::encode_all(text.as_bytes(), 0).unwrap();
+        assert_eq!(text, decompress(Compression::Zstd, &zstd));
+
+        let mut bzip2 = bzip2::write::BzEncoder::new(Vec::new(), bzip2::Compression::default());
+        bzip2.write_all(text.as_bytes()).unwrap();
+        assert_eq!(
+            text,
+            decompress(Compression::Bzip2, &bzip2.finish().unwrap())
+        );
+    }
+}
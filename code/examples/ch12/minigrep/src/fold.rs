@@ -0,0 +1,295 @@
+use std::cell::RefCell;
+use std::ops::Range;
+
+use aho_corasick::{AhoCorasick, BuildError, Input, MatchKind};
+
+use crate::Matcher;
+
+/// A query under full Unicode case folding, so `ß` finds `SS`, `ς` finds `Σ`
+/// and `İ` finds `i̇`.
+///
+/// The query is folded once up front; lines are folded a char at a time while
+/// comparing, so searching never allocates. Matches always start and end on
+/// char boundaries of the line, so their spans slice the original text.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FoldedQuery {
+    chars: Vec<char>,
+}
+
+impl FoldedQuery {
+    pub fn new(query: &str) -> Self {
+        Self {
+            chars: query.chars().flat_map(fold).collect(),
+        }
+    }
+
+    /// How many bytes at the start of `text` fold to exactly the query.
+    fn match_len(&self, text: &str) -> Option<usize> {
+        let mut wanted = self.chars.iter();
+        let mut next = wanted.next();
+
+        for (i, c) in text.char_indices() {
+            for folded in fold(c) {
+                // A char whose folding runs past the end of the query doesn't count
+                match next {
+                    Some(&w) if w == folded => next = wanted.next(),
+                    _ => return None,
+                }
+            }
+            if next.is_none() {
+                return Some(i + c.len_utf8());
+            }
+        }
+
+        None
+    }
+}
+
+impl Matcher for FoldedQuery {
+    /// An empty query matches straight away, with an empty span.
+    fn find_at(&self, line: &str, from: usize) -> Option<Range<usize>> {
+        if self.chars.is_empty() {
+            return Some(from..from);
+        }
+
+        line[from..].char_indices().find_map(|(i, _)| {
+            let start = from + i;
+            self.match_len(&line[start..]).map(|len| start..start + len)
+        })
+    }
+}
+
+/// Many queries under full case folding, all searched for in a single pass.
+///
+/// The patterns are folded once and put in an Aho–Corasick automaton, and each
+/// line is folded into a reused per-thread buffer before running it, so this
+/// doesn't allocate per line either.
+#[derive(Debug, Clone)]
+pub struct FoldedQuerySet {
+    automaton: AhoCorasick,
+}
+
+thread_local! {
+    /// The folded line, and for each of its bytes where that char began in the line
+    static FOLDED_LINE: RefCell<(String, Vec<usize>)> = RefCell::default();
+}
+
+impl FoldedQuerySet {
+    pub fn new<I, P>(patterns: I) -> Result<Self, BuildError>
+    where
+        I: IntoIterator<Item = P>,
+        P: AsRef<str>,
+    {
+        let folded = patterns
+            .into_iter()
+            .map(|p| p.as_ref().chars().flat_map(fold).collect::<String>());
+        let automaton = AhoCorasick::builder()
+            .match_kind(MatchKind::LeftmostLongest)
+            .build(folded)?;
+
+        Ok(Self { automaton })
+    }
+}
+
+impl FoldedQuerySet {
+    /// Every match in `line` that `is_wanted` accepts, left to right, folding
+    /// the line just once.
+    ///
+    /// After a match it doesn't accept, the search goes on from the next char
+    /// rather than the end of that match, as `-w` needs.
+    pub fn find_all_where(
+        &self,
+        line: &str,
+        is_wanted: impl Fn(&Range<usize>) -> bool,
+    ) -> Vec<Range<usize>> {
+        let mut spans = Vec::new();
+        self.for_each_match(line, 0, is_wanted, |span| {
+            spans.push(span);
+            true
+        });
+        spans
+    }
+
+    /// Folds `line` from byte `from` on, once, then calls `each` with every
+    /// match in it that `is_wanted` accepts, left to right, until it returns
+    /// false.
+    fn for_each_match(
+        &self,
+        line: &str,
+        from: usize,
+        is_wanted: impl Fn(&Range<usize>) -> bool,
+        mut each: impl FnMut(Range<usize>) -> bool,
+    ) {
+        FOLDED_LINE.with_borrow_mut(|(folded, origin)| {
+            folded.clear();
+            origin.clear();
+            for (i, c) in line[from..].char_indices() {
+                for f in fold(c) {
+                    folded.push(f);
+                    origin.resize(folded.len(), from + i);
+                }
+            }
+            origin.push(line.len());
+
+            // Where the folding of one of the line's chars starts
+            let on_boundary = |at: usize| at == 0 || origin[at] != origin[at - 1];
+
+            let mut at = 0;
+            while at <= folded.len() {
+                let Some(found) = self
+                    .automaton
+                    .find(Input::new(folded.as_str()).span(at..folded.len()))
+                else {
+                    return;
+                };
+                let span = origin[found.start()]..origin[found.end()];
+                if !on_boundary(found.start()) || !on_boundary(found.end()) || !is_wanted(&span) {
+                    // Look again from the next folded char, the next
+                    // candidate may overlap this one
+                    at = found.start()
+                        + folded[found.start()..]
+                            .chars()
+                            .next()
+                            .map_or(1, char::len_utf8);
+                    continue;
+                }
+                if !each(span) {
+                    return;
+                }
+                at = found.end();
+                if found.is_empty() {
+                    // Step over a char, so an empty match isn't found again
+                    at += folded[at..].chars().next().map_or(1, char::len_utf8);
+                }
+            }
+        });
+    }
+}
+
+impl Matcher for FoldedQuerySet {
+    /// The first (longest) match starting at or after byte `from` of `line`.
+    fn find_at(&self, line: &str, from: usize) -> Option<Range<usize>> {
+        let mut first = None;
+        self.for_each_match(
+            line,
+            from,
+            |_| true,
+            |span| {
+                first = Some(span);
+                false
+            },
+        );
+        first
+    }
+
+    /// Folds `line` just once, rather than again from the end of each match.
+    fn find_all(&self, line: &str) -> Vec<Range<usize>> {
+        self.find_all_where(line, |_| true)
+    }
+}
+
+/// Folds one char, yielding up to three chars and never allocating.
+///
+/// std only exposes case *conversion*, but lowercasing a char's uppercase form
+/// sorts every char into the same classes as CaseFolding.txt's full folding,
+/// with two exceptions handled here: dotless `ı` has no folding at all, and
+/// `ẞ` uppercases to itself so has to go via `ß` to reach `ss`.
+pub fn fold(c: char) -> impl Iterator<Item = char> {
+    let unchanged = (c == 'ı').then_some(c);
+    let c = if c == 'ẞ' { 'ß' } else { c };
+    let converted = unchanged.is_none().then(|| c.to_uppercase());
+
+    unchanged
+        .into_iter()
+        .chain(converted.into_iter().flatten().flat_map(char::to_lowercase))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn sharp_s_and_double_s() {
+        let query = FoldedQuery::new("STRASSE");
+        assert!(query.is_match("Die Straße"));
+        assert!(query.is_match("DIE STRAẞE"));
+        assert_eq!(vec![4..11], query.find_spans("Die Straße"));
+
+        // Half of a folded ß is not a match on its own
+        assert!(!FoldedQuery::new("s").is_match("ß"));
+    }
+
+    #[test]
+    fn greek_final_sigma() {
+        let query = FoldedQuery::new("ὈΔΥΣΣΕΎΣ");
+        assert!(query.is_match("ὀδυσσεύς"));
+        assert!(FoldedQuery::new("σ").is_match("λόγος"));
+    }
+
+    #[test]
+    fn turkish_dotted_and_dotless_i() {
+        assert!(FoldedQuery::new("i\u{307}stanbul").is_match("İSTANBUL"));
+        assert!(!FoldedQuery::new("i").is_match("ı"));
+        assert!(FoldedQuery::new("ı").is_match("ı"));
+    }
+
+    #[test]
+    fn spans_slice_the_original_line() {
+        let line = "İx ẞ x";
+        let spans = FoldedQuery::new("X").find_spans(line);
+        assert_eq!(
+            vec!["x", "x"],
+            spans.iter().map(|s| &line[s.clone()]).collect::<Vec<_>>()
+        );
+
+        let spans = FoldedQuery::new("ss").find_spans(line);
+        assert_eq!(
+            vec!["ẞ"],
+            spans.iter().map(|s| &line[s.clone()]).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn query_set_finds_the_longest_folded_match() {
+        let set = FoldedQuerySet::new(["ROSE", "strasse", "Straßenbahn"]).unwrap();
+        let line = "Die STRASSENBAHN, eine Rose";
+        assert_eq!(Some(4..16), set.find_at(line, 0));
+        assert_eq!(Some(23..27), set.find_at(line, 16));
+        assert_eq!(None, set.find_at(line, 24));
+
+        // Half of a folded ß doesn't count here either
+        let set = FoldedQuerySet::new(["sx", "x"]).unwrap();
+        assert_eq!(Some(2..3), set.find_at("ßx", 0));
+        assert!(!FoldedQuerySet::new(["s"]).unwrap().is_match("ß"));
+    }
+
+    #[test]
+    fn query_set_finds_every_match_in_one_pass() {
+        let set = FoldedQuerySet::new(["foo", "PIN", "ss"]).unwrap();
+        let line = "Foo, spin ß and FOOSS";
+        assert_eq!(
+            vec![0..3, 6..9, 10..12, 17..20, 20..22],
+            set.find_spans(line)
+        );
+
+        // Highlighting a long line used to fold the rest of it again after
+        // every match, taking minutes here rather than milliseconds
+        let line = "foo pin ".repeat(25_000);
+        let started = Instant::now();
+        assert_eq!(50_000, set.find_spans(&line).len());
+        assert!(started.elapsed() < Duration::from_secs(5));
+
+        // Empty matches at every char, but none inside a folded ß
+        let set = FoldedQuerySet::new(["", "x"]).unwrap();
+        assert_eq!(vec![0..0, 2..3, 3..3], set.find_all("ßx"));
+    }
+
+    #[test]
+    fn empty_query_matches_without_spans() {
+        let query = FoldedQuery::new("");
+        assert!(query.is_match("anything"));
+        assert!(query.find_spans("anything").is_empty());
+    }
+}
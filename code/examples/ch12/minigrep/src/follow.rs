@@ -0,0 +1,190 @@
+use std::fs::{self, File, Metadata};
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+use crate::{Matcher, Searcher, Sink};
+
+/// How long to wait before looking for more input at the end of a file.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Reads a file that's still being written to, like `tail -F`.
+///
+/// At the end of the file it waits for more rather than stopping. If the file
+/// is truncated, or replaced by a new one as log rotation does, the read
+/// that notices ends the input; [`Tail::restarted`] then says to carry on
+/// from the start of the new contents.
+pub struct Tail {
+    path: PathBuf,
+    file: File,
+    position: u64,
+    poll: Duration,
+    restarted: bool,
+}
+
+impl Tail {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        Ok(Self {
+            path: path.to_path_buf(),
+            file: File::open(path)?,
+            position: 0,
+            poll: POLL_INTERVAL,
+            restarted: false,
+        })
+    }
+
+    /// Whether the input last ended because the file started over. Asking
+    /// resets it.
+    pub fn restarted(&mut self) -> bool {
+        std::mem::take(&mut self.restarted)
+    }
+
+    /// Looks at what's at the path now, once the open file has run out.
+    /// Returns whether reading should start over.
+    fn check_path(&mut self) -> io::Result<bool> {
+        let current = match fs::metadata(&self.path) {
+            Ok(current) => current,
+            // Moved away and not created again yet
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(e),
+        };
+
+        if !is_same_file(&current, &self.file.metadata()?) {
+            self.file = File::open(&self.path)?;
+        } else if current.len() < self.position {
+            self.file.seek(SeekFrom::Start(0))?;
+        } else {
+            return Ok(false);
+        }
+        self.position = 0;
+        Ok(true)
+    }
+}
+
+impl Read for Tail {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let read = self.file.read(buf)?;
+            if read > 0 {
+                self.position += read as u64;
+                return Ok(read);
+            }
+            // Lines written to the old file just before it was rotated have
+            // already been read, since it came up empty above
+            if self.check_path()? {
+                self.restarted = true;
+                return Ok(0);
+            }
+            thread::sleep(self.poll);
+        }
+    }
+}
+
+#[cfg(unix)]
+fn is_same_file(a: &Metadata, b: &Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    (a.dev(), a.ino()) == (b.dev(), b.ino())
+}
+
+/// Without inodes to compare, only truncation can be noticed.
+#[cfg(not(unix))]
+fn is_same_file(_: &Metadata, _: &Metadata) -> bool {
+    true
+}
+
+/// Searches `tail` as it grows, for as long as it's read, with line numbers
+/// and byte offsets starting over whenever the file does. Returns how many
+/// lines were selected in all.
+pub fn search_following<M, S>(
+    searcher: &Searcher,
+    matcher: &M,
+    path: &Path,
+    mut tail: Tail,
+    sink: &mut S,
+) -> io::Result<usize>
+where
+    M: Matcher + ?Sized,
+    S: Sink + ?Sized,
+{
+    sink.begin(path)?;
+
+    let mut selected = 0;
+    loop {
+        // -m counts every line selected since following began
+        let mut segment = *searcher;
+        segment.options.max_count = searcher.options.max_count.map(|max| max - selected);
+        selected += segment.search_reader(matcher, BufReader::new(&mut tail), sink)?;
+
+        let limit_reached = searcher
+            .options
+            .max_count
+            .is_some_and(|max| selected >= max);
+        if limit_reached || !tail.restarted() {
+            break;
+        }
+    }
+
+    sink.finish(path, selected)?;
+    Ok(selected)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Write;
+    use std::sync::mpsc;
+
+    use crate::testutil::TempDir;
+    use crate::Match;
+
+    /// Passes each selected line on as it comes.
+    struct Lines(mpsc::Sender<(usize, String)>);
+
+    impl Sink for Lines {
+        fn matched(&mut self, m: &Match) -> io::Result<bool> {
+            _ = self.0.send((m.line_number, m.line.to_string()));
+            Ok(true)
+        }
+    }
+
+    #[test]
+    fn follows_appends_truncation_and_rotation() {
+        let dir = TempDir::new("follow");
+        let path = dir.join("app.log");
+        fs::write(&path, "old match\nnoise\n").unwrap();
+
+        let mut tail = Tail::open(&path).unwrap();
+        tail.poll = Duration::from_millis(5);
+        // Following only ends once -m lines have been found
+        let mut searcher = Searcher::default();
+        searcher.options.max_count = Some(4);
+        let (sender, found) = mpsc::channel();
+
+        let follower = thread::spawn({
+            let path = path.clone();
+            move || search_following(&searcher, "match", &path, tail, &mut Lines(sender))
+        });
+        let next = || found.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!((1, String::from("old match")), next());
+
+        // A line only counts once it's finished
+        let mut log = File::options().append(true).open(&path).unwrap();
+        write!(log, "appended ").unwrap();
+        thread::sleep(Duration::from_millis(50));
+        writeln!(log, "match").unwrap();
+        assert_eq!((3, String::from("appended match")), next());
+
+        // Shorter than before, so it's known to be truncated
+        fs::write(&path, "match 2\n").unwrap();
+        assert_eq!((1, String::from("match 2")), next());
+
+        fs::rename(&path, dir.join("app.log.1")).unwrap();
+        thread::sleep(Duration::from_millis(50));
+        fs::write(&path, "noise\nrotated match\n").unwrap();
+        assert_eq!((2, String::from("rotated match")), next());
+
+        assert_eq!(4, follower.join().unwrap().unwrap());
+    }
+}
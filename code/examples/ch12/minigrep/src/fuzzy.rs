@@ -0,0 +1,160 @@
+use std::ops::Range;
+
+use crate::fold::fold;
+use crate::Matcher;
+
+/// A query that also matches any substring within `max_distance` edits of it,
+/// counting each inserted, deleted or substituted char as one edit.
+///
+/// Lines are scanned with Sellers' variant of the Levenshtein table, where a
+/// match may start anywhere, keeping one column of the table at a time.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FuzzyQuery {
+    chars: Vec<char>,
+    max_distance: usize,
+    ignore_case: bool,
+}
+
+/// One cell of the table: the fewest edits for the query's first chars to
+/// end at this point of the line, and where in the line that match starts.
+#[derive(Debug, Clone, Copy)]
+struct Cell {
+    cost: usize,
+    start: usize,
+}
+
+impl FuzzyQuery {
+    pub fn new(query: &str, max_distance: usize, ignore_case: bool) -> Self {
+        Self {
+            chars: query.chars().collect(),
+            max_distance,
+            ignore_case,
+        }
+    }
+
+    /// Runs the table over `line[from..]`, calling `on_end` with the cost and
+    /// span of the best match ending at each char boundary until it returns
+    /// false.
+    fn scan(&self, line: &str, from: usize, mut on_end: impl FnMut(usize, Range<usize>) -> bool) {
+        let mut column: Vec<Cell> = (0..=self.chars.len())
+            .map(|cost| Cell { cost, start: from })
+            .collect();
+        let last = column[self.chars.len()];
+        if !on_end(last.cost, last.start..from) {
+            return;
+        }
+
+        for (i, c) in line[from..].char_indices() {
+            let end = from + i + c.len_utf8();
+            // The cell to the upper left, before this column overwrites it
+            let mut diagonal = column[0];
+            column[0] = Cell {
+                cost: 0,
+                start: end,
+            };
+
+            for (j, &q) in self.chars.iter().enumerate() {
+                let substituted = Cell {
+                    cost: diagonal.cost + usize::from(!self.chars_match(q, c)),
+                    start: diagonal.start,
+                };
+                let skipped_line_char = Cell {
+                    cost: column[j + 1].cost + 1,
+                    ..column[j + 1]
+                };
+                let skipped_query_char = Cell {
+                    cost: column[j].cost + 1,
+                    ..column[j]
+                };
+                diagonal = column[j + 1];
+                // On a tie, prefer the match that starts later, so it's tighter
+                column[j + 1] = [substituted, skipped_query_char, skipped_line_char]
+                    .into_iter()
+                    .min_by_key(|cell| (cell.cost, usize::MAX - cell.start))
+                    .unwrap_or(substituted);
+            }
+
+            let last = column[self.chars.len()];
+            if !on_end(last.cost, last.start..end) {
+                return;
+            }
+        }
+    }
+
+    fn chars_match(&self, a: char, b: char) -> bool {
+        a == b || (self.ignore_case && fold(a).eq(fold(b)))
+    }
+}
+
+impl Matcher for FuzzyQuery {
+    fn is_match(&self, line: &str) -> bool {
+        self.distance(line).is_some()
+    }
+
+    /// The fewest edits that turn some substring of `line` into the query,
+    /// if that's within the limit.
+    fn distance(&self, line: &str) -> Option<usize> {
+        let mut best = self.chars.len();
+        self.scan(line, 0, |cost, _| {
+            best = best.min(cost);
+            best > 0
+        });
+        (best <= self.max_distance).then_some(best)
+    }
+
+    /// The first close enough match at or after byte `from` of `line`: of the
+    /// run of match ends that are within the limit, the closest and then the
+    /// longest.
+    fn find_at(&self, line: &str, from: usize) -> Option<Range<usize>> {
+        let mut found: Option<(usize, Range<usize>)> = None;
+        self.scan(line, from, |cost, span| {
+            if cost > self.max_distance {
+                // Keep looking until a match turns up, then stop where its run ends
+                return found.is_none();
+            }
+            // Take a closer match, or a wider one that's no further off
+            let better = found.as_ref().is_none_or(|(best, best_span)| {
+                cost < *best || (cost == *best && span.start == best_span.start)
+            });
+            if better {
+                found = Some((cost, span));
+            }
+            cost > 0
+        });
+        found.map(|(_, span)| span)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_substrings_within_the_distance() {
+        let query = FuzzyQuery::new("needle", 1, false);
+        assert_eq!(Some(0), query.distance("a needle here"));
+        assert_eq!(Some(1), query.distance("a neddle here"));
+        assert_eq!(Some(1), query.distance("a neele here"));
+        assert_eq!(Some(0), query.distance("a nneedle here"));
+        assert_eq!(None, query.distance("a nedl here"));
+
+        assert_eq!(Some(2..7), query.find_at("a neele here", 0));
+        assert_eq!(None, query.find_at("a neele here", 3));
+    }
+
+    #[test]
+    fn prefers_the_closest_match() {
+        let query = FuzzyQuery::new("abcd", 2, false);
+        // "ab" is already within 2, but the whole of "abxd" is within 1
+        assert_eq!(Some(0..4), query.find_at("abxd", 0));
+    }
+
+    #[test]
+    fn spans_are_char_boundaries() {
+        let query = FuzzyQuery::new("café", 1, true);
+        let line = "Un CAFE noir";
+        let span = query.find_at(line, 0).unwrap();
+        assert_eq!("CAFE", &line[span]);
+        assert_eq!(Some(0), query.distance("CAFÉ"));
+    }
+}
@@ -0,0 +1,485 @@
+use std::collections::HashMap;
+use std::fs::{self, Metadata};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use ignore::WalkBuilder;
+
+use crate::fold::fold;
+use crate::replace::write_atomically;
+use crate::{Config, OutputMode, STDIN_PATH};
+
+/// The index of a directory is kept in this file at its top.
+pub const INDEX_FILE: &str = ".minigrep-index";
+
+/// Starts every index, so one written by some other version is never misread.
+const MAGIC: &[u8] = b"minigrep-index 2\n";
+
+/// Which three-byte sequences occur in each file under a directory, so a
+/// search can skip the files that can't contain its query.
+///
+/// Trigrams are taken from the text under full case folding, which keeps them
+/// usable with and without `-i`. Each file also records the modification time
+/// and length it had when it was read; once either has changed, the entry says
+/// nothing and the file is searched as usual.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Index {
+    files: HashMap<String, Entry>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Entry {
+    stamp: Stamp,
+    /// Sorted, with the three bytes packed into the low 24 bits of each
+    trigrams: Vec<u32>,
+}
+
+/// What a file looked like when it was indexed.
+///
+/// Where there are inodes and change times, a file replaced by another, or
+/// with its modification time set back, counts as changed too. An edit that
+/// keeps the length and lands within the same tick of the file system's
+/// clock as the indexing still goes unnoticed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Stamp {
+    modified_secs: u64,
+    modified_nanos: u32,
+    len: u64,
+    changed_secs: u64,
+    changed_nanos: u32,
+    inode: u64,
+}
+
+impl Stamp {
+    fn of(metadata: &Metadata) -> io::Result<Self> {
+        let modified = metadata.modified()?;
+        let modified = modified.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let (changed_secs, changed_nanos, inode) = changed_and_inode(metadata);
+        Ok(Self {
+            modified_secs: modified.as_secs(),
+            modified_nanos: modified.subsec_nanos(),
+            len: metadata.len(),
+            changed_secs,
+            changed_nanos,
+            inode,
+        })
+    }
+}
+
+#[cfg(unix)]
+fn changed_and_inode(metadata: &Metadata) -> (u64, u32, u64) {
+    use std::os::unix::fs::MetadataExt;
+    (
+        metadata.ctime().try_into().unwrap_or_default(),
+        metadata.ctime_nsec().try_into().unwrap_or_default(),
+        metadata.ino(),
+    )
+}
+
+#[cfg(not(unix))]
+fn changed_and_inode(_: &Metadata) -> (u64, u32, u64) {
+    (0, 0, 0)
+}
+
+/// What `Index::update` did: how many files it indexed, and how many of
+/// those it had to read because they were new or had changed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct IndexStats {
+    pub files: usize,
+    pub updated: usize,
+}
+
+impl Index {
+    /// Reads the index at the top of `dir`.
+    pub fn load(dir: &Path) -> io::Result<Self> {
+        let path = dir.join(INDEX_FILE);
+        let bytes = fs::read(&path)?;
+        Self::decode(&bytes).ok_or_else(|| {
+            let message = format!("{}: not a minigrep index", path.display());
+            io::Error::new(io::ErrorKind::InvalidData, message)
+        })
+    }
+
+    /// Indexes the files under `dir` and saves the index at its top. Files
+    /// already in an earlier index are only read again if they've changed.
+    ///
+    /// The same files are indexed as `-r` would search by default, so hidden
+    /// and ignored files are left out. So is anything that can't be read or
+    /// whose path isn't UTF-8; searches don't skip files they can't look up.
+    pub fn update(dir: &Path) -> io::Result<IndexStats> {
+        // Anything wrong with the old index just means starting from scratch
+        let mut old = Self::load(dir).unwrap_or_default();
+        let mut index = Self::default();
+        let mut stats = IndexStats::default();
+        let mut seen = TrigramSet::new();
+
+        let walker = WalkBuilder::new(dir)
+            .require_git(false)
+            .sort_by_file_name(|a, b| a.cmp(b))
+            .build();
+        for entry in walker.flatten() {
+            if !entry.file_type().is_some_and(|t| t.is_file()) {
+                continue;
+            }
+            let Some(name) = relative_name(dir, entry.path()) else {
+                continue;
+            };
+            // Stamped before reading, so a change made meanwhile is noticed later
+            let Ok(stamp) = entry
+                .metadata()
+                .map_err(io::Error::other)
+                .and_then(|m| Stamp::of(&m))
+            else {
+                continue;
+            };
+
+            let entry = match old.files.remove(name) {
+                Some(unchanged) if unchanged.stamp == stamp => unchanged,
+                _ => {
+                    let Ok(contents) = fs::read(entry.path()) else {
+                        continue;
+                    };
+                    stats.updated += 1;
+                    Entry {
+                        stamp,
+                        trigrams: seen.collect(&String::from_utf8_lossy(&contents)),
+                    }
+                }
+            };
+            index.files.insert(name.to_string(), entry);
+            stats.files += 1;
+        }
+
+        write_atomically(&dir.join(INDEX_FILE), &index.encode())?;
+        Ok(stats)
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut names: Vec<&String> = self.files.keys().collect();
+        names.sort();
+
+        let mut out = MAGIC.to_vec();
+        write_varint(&mut out, names.len() as u64);
+        for name in names {
+            let entry = &self.files[name];
+            write_varint(&mut out, name.len() as u64);
+            out.extend_from_slice(name.as_bytes());
+            write_varint(&mut out, entry.stamp.modified_secs);
+            write_varint(&mut out, entry.stamp.modified_nanos.into());
+            write_varint(&mut out, entry.stamp.len);
+            write_varint(&mut out, entry.stamp.changed_secs);
+            write_varint(&mut out, entry.stamp.changed_nanos.into());
+            write_varint(&mut out, entry.stamp.inode);
+            write_varint(&mut out, entry.trigrams.len() as u64);
+            // Sorted, so storing the gaps keeps most of them to a byte
+            let mut previous = 0;
+            for &trigram in &entry.trigrams {
+                write_varint(&mut out, (trigram - previous).into());
+                previous = trigram;
+            }
+        }
+        out
+    }
+
+    fn decode(bytes: &[u8]) -> Option<Self> {
+        let mut bytes = bytes.strip_prefix(MAGIC)?;
+        let mut files = HashMap::new();
+
+        for _ in 0..read_varint(&mut bytes)? {
+            let len = usize::try_from(read_varint(&mut bytes)?).ok()?;
+            let (name, rest) = bytes.split_at_checked(len)?;
+            bytes = rest;
+            let stamp = Stamp {
+                modified_secs: read_varint(&mut bytes)?,
+                modified_nanos: read_varint(&mut bytes)?.try_into().ok()?,
+                len: read_varint(&mut bytes)?,
+                changed_secs: read_varint(&mut bytes)?,
+                changed_nanos: read_varint(&mut bytes)?.try_into().ok()?,
+                inode: read_varint(&mut bytes)?,
+            };
+            let mut trigrams = Vec::new();
+            let mut previous = 0u32;
+            for _ in 0..read_varint(&mut bytes)? {
+                let gap = u32::try_from(read_varint(&mut bytes)?).ok()?;
+                previous = previous.checked_add(gap)?;
+                trigrams.push(previous);
+            }
+            let name = String::from_utf8(name.to_vec()).ok()?;
+            files.insert(name, Entry { stamp, trigrams });
+        }
+
+        bytes.is_empty().then_some(Self { files })
+    }
+}
+
+/// The name `path` is indexed under in the index of `dir`.
+fn relative_name<'a>(dir: &Path, path: &'a Path) -> Option<&'a str> {
+    path.strip_prefix(dir).ok()?.to_str()
+}
+
+/// Marks which trigrams have been seen, one bit for each possible trigram,
+/// so gathering a large file's trigrams needs no list as long as the file.
+struct TrigramSet {
+    bits: Vec<u64>,
+}
+
+impl TrigramSet {
+    fn new() -> Self {
+        Self {
+            bits: vec![0; (1 << 24) / 64],
+        }
+    }
+
+    /// The distinct trigrams of `text` under case folding, in order. The set
+    /// is left empty again for the next file.
+    fn collect(&mut self, text: &str) -> Vec<u32> {
+        let mut trigrams = Vec::new();
+        let mut window = 0u32;
+        let mut len = 0;
+        let mut buf = [0; 4];
+        for c in text.chars().flat_map(fold) {
+            for &byte in c.encode_utf8(&mut buf).as_bytes() {
+                window = (window << 8 | u32::from(byte)) & 0xff_ffff;
+                len += 1;
+                let (word, bit) = (window as usize / 64, 1 << (window % 64));
+                if len >= 3 && self.bits[word] & bit == 0 {
+                    self.bits[word] |= bit;
+                    trigrams.push(window);
+                }
+            }
+        }
+
+        for &trigram in &trigrams {
+            self.bits[trigram as usize / 64] = 0;
+        }
+        trigrams.sort_unstable();
+        trigrams
+    }
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn read_varint(bytes: &mut &[u8]) -> Option<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let (&byte, rest) = bytes.split_first()?;
+        *bytes = rest;
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte < 0x80 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+/// Picks out the files a search can skip, going by the index of the nearest
+/// directory above each that has one.
+///
+/// Only directories at or below the paths being searched are looked in, so
+/// an index somewhere further up, say a stray one in the home directory, is
+/// never trusted without asking.
+pub struct IndexFilter {
+    /// For each query, the trigrams a file must contain to match it
+    queries: Vec<Vec<u32>>,
+    /// The paths being searched, which the search for an index stops at
+    roots: Vec<PathBuf>,
+    /// Each directory looked at so far, and its index if it has one
+    indexes: HashMap<PathBuf, Option<Index>>,
+}
+
+impl IndexFilter {
+    /// A filter for the search `config` asks for, unless an index can't tell
+    /// anything about it: a query shorter than three bytes, or a regex that
+    /// isn't a plain literal, could match nearly anything; and `-v`, `-c`,
+    /// `-L` and `--follow` want to hear about files without matches too.
+    pub fn new(config: &Config) -> Option<Self> {
+        let reports_every_file = config.invert_match
+            || config.follow
+            || matches!(
+                config.output,
+                OutputMode::Count | OutputMode::FilesWithoutMatch
+            );
+        // The index only knows the files' contents as they are on disk
+        let transformed = config.search_zip || config.encoding.is_some();
+        // The regex engine's case folding is its own, so only trust exact ones
+        let unindexable_regex = config.regex
+            && (config.ignore_case || config.patterns.iter().any(|p| regex::escape(p) != *p));
+        if reports_every_file || transformed || unindexable_regex || config.fuzzy.is_some() {
+            return None;
+        }
+
+        let mut seen = TrigramSet::new();
+        let queries: Vec<Vec<u32>> = config.patterns.iter().map(|p| seen.collect(p)).collect();
+        if queries.is_empty() || queries.iter().any(Vec::is_empty) {
+            return None;
+        }
+
+        let roots = config
+            .file_paths
+            .iter()
+            .filter(|path| *path != STDIN_PATH)
+            .map(PathBuf::from)
+            .collect();
+        Some(Self {
+            queries,
+            roots,
+            indexes: HashMap::new(),
+        })
+    }
+
+    /// Whether `path` could contain a match. Only files an index knows about,
+    /// and that haven't changed since, can be ruled out.
+    pub fn may_match(&mut self, path: &Path) -> bool {
+        let Some(root) = self.roots.iter().find(|root| path.starts_with(root)) else {
+            return true;
+        };
+
+        for dir in path.ancestors().skip(1) {
+            if !dir.starts_with(root) {
+                break;
+            }
+            let index = self
+                .indexes
+                .entry(dir.to_path_buf())
+                .or_insert_with(|| Index::load(dir).ok());
+            let Some(index) = index else {
+                continue;
+            };
+            let Some(entry) = relative_name(dir, path).and_then(|name| index.files.get(name))
+            else {
+                return true;
+            };
+            // Only looked at once there's an entry, which it has to match
+            let unchanged = fs::metadata(path)
+                .and_then(|m| Stamp::of(&m))
+                .is_ok_and(|stamp| stamp == entry.stamp);
+            return !unchanged
+                || self.queries.iter().any(|query| {
+                    let contains = |trigram| entry.trigrams.binary_search(trigram).is_ok();
+                    query.iter().all(contains)
+                });
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs::File;
+
+    use crate::testutil::{build_config, TempDir};
+
+    fn filter(flags: &[&str], dir: &Path) -> Option<IndexFilter> {
+        let args = ["minigrep", "-r"]
+            .iter()
+            .chain(flags)
+            .map(|a| a.to_string())
+            .chain([dir.display().to_string()]);
+        IndexFilter::new(&build_config(args).unwrap())
+    }
+
+    #[test]
+    fn updates_only_changed_files() {
+        let dir = TempDir::new("index");
+        fs::create_dir(dir.join("nested")).unwrap();
+        fs::write(dir.join("a.txt"), "Hello, World").unwrap();
+        fs::write(dir.join("nested/b.txt"), "something else").unwrap();
+
+        assert_eq!(
+            IndexStats {
+                files: 2,
+                updated: 2
+            },
+            Index::update(&dir).unwrap()
+        );
+        assert_eq!(
+            IndexStats {
+                files: 2,
+                updated: 0
+            },
+            Index::update(&dir).unwrap()
+        );
+        fs::write(dir.join("nested/b.txt"), "something new").unwrap();
+        fs::remove_file(dir.join("a.txt")).unwrap();
+        assert_eq!(
+            IndexStats {
+                files: 1,
+                updated: 1
+            },
+            Index::update(&dir).unwrap()
+        );
+
+        let index = Index::load(&dir).unwrap();
+        assert_eq!(vec!["nested/b.txt"], index.files.keys().collect::<Vec<_>>());
+        assert_eq!(
+            Some(index),
+            Index::decode(&Index::load(&dir).unwrap().encode())
+        );
+    }
+
+    #[test]
+    fn skips_files_that_cannot_match() {
+        let dir = TempDir::new("index-skip");
+        fs::write(dir.join("a.txt"), "Hello, World").unwrap();
+        fs::write(dir.join("b.txt"), "STRASSE").unwrap();
+        Index::update(&dir).unwrap();
+
+        let mut world = filter(&["world"], &dir).unwrap();
+        assert!(world.may_match(&dir.join("a.txt")));
+        assert!(!world.may_match(&dir.join("b.txt")));
+
+        // Folded trigrams work with -i, all the way to ß
+        let mut street = filter(&["-i", "-e", "straße", "-e", "nothing"], &dir).unwrap();
+        assert!(!street.may_match(&dir.join("a.txt")));
+        assert!(street.may_match(&dir.join("b.txt")));
+
+        // A file changed since it was indexed is searched again
+        fs::write(dir.join("b.txt"), "Brave new world").unwrap();
+        assert!(world.may_match(&dir.join("b.txt")));
+
+        // An index above the searched directory isn't used
+        fs::create_dir(dir.join("nested")).unwrap();
+        fs::write(dir.join("nested/c.txt"), "STRASSE").unwrap();
+        Index::update(&dir).unwrap();
+        let mut nested = filter(&["world"], &dir.join("nested")).unwrap();
+        assert!(nested.may_match(&dir.join("nested/c.txt")));
+        let mut world = filter(&["world"], &dir).unwrap();
+        assert!(!world.may_match(&dir.join("nested/c.txt")));
+
+        assert!(filter(&["wo"], &dir).is_none());
+        assert!(filter(&["-E", "w.rld"], &dir).is_none());
+        assert!(filter(&["-c", "world"], &dir).is_none());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn notices_a_file_replaced_by_one_that_looks_the_same() {
+        let dir = TempDir::new("index-replaced");
+        let path = dir.join("a.txt");
+        fs::write(&path, "Hello, there").unwrap();
+        Index::update(&dir).unwrap();
+        let modified = fs::metadata(&path).unwrap().modified().unwrap();
+
+        // Same length and modification time, as an editor saving quickly might leave it
+        let replacement = dir.join("a.txt.new");
+        fs::write(&replacement, "Hello, World").unwrap();
+        File::options()
+            .write(true)
+            .open(&replacement)
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+        fs::rename(&replacement, &path).unwrap();
+
+        assert!(filter(&["World"], &dir).unwrap().may_match(&path));
+    }
+}
@@ -1,74 +1,1102 @@
+use std::collections::VecDeque;
 use std::env;
 use std::error::Error;
 use std::fs;
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::num::NonZeroUsize;
+use std::ops::Range;
+use std::path::Path;
+use std::str::FromStr;
+use std::thread;
+use std::time::Instant;
+
+use encoding_rs::Encoding;
+use globset::GlobSet;
+use regex::Regex;
+
+pub use args::ArgsError;
+use args::{Arg, Parser};
+pub use fold::{FoldedQuery, FoldedQuerySet};
+pub use fuzzy::FuzzyQuery;
+use index::IndexFilter;
+pub use index::{Index, IndexStats};
+pub use literal::Contents;
+pub use matcher::{Matcher, QueryMatcher};
+use printer::Printer;
+pub use searcher::{Input, Searcher, Sink};
+pub use settings::{ColorScheme, Settings};
+pub use walk::{collect_files, FileEntry};
+
+mod args;
+mod decompress;
+mod fold;
+mod follow;
+mod fuzzy;
+mod index;
+mod literal;
+mod matcher;
+mod multiline;
+mod pool;
+mod printer;
+mod replace;
+mod searcher;
+mod settings;
+#[cfg(test)]
+mod testutil;
+mod tui;
+mod walk;
 
 pub struct Config {
-    pub query: String,
-    pub file_path: String,
+    /// A line is selected if any of these match it
+    pub patterns: Vec<String>,
+    pub file_paths: Vec<String>,
     pub ignore_case: bool,
+    pub regex: bool,
+    pub word_regexp: bool,
+    pub line_regexp: bool,
+    /// Also match anything up to this many edits away (`--fuzzy`)
+    pub fuzzy: Option<usize>,
+    pub recursive: bool,
+    /// Also search hidden files and directories when walking (`--hidden`)
+    pub hidden: bool,
+    /// Search files even if `.gitignore` or `.ignore` lists them (`--no-ignore`)
+    pub no_ignore: bool,
+    /// Only search files whose name matches one of these (`--include`)
+    pub include: GlobSet,
+    /// Never search files whose name matches one of these (`--exclude`)
+    pub exclude: GlobSet,
+    /// Extra `.gitignore`-style rules from the config file, used when walking
+    pub ignore: Vec<String>,
+    pub line_number: bool,
+    pub byte_offset: bool,
+    pub context: Context,
+    pub invert_match: bool,
+    pub max_count: Option<usize>,
+    pub output: OutputMode,
+    pub color: ColorChoice,
+    pub colors: ColorScheme,
+    /// How many files to search at once (`-j`)
+    pub threads: usize,
+    /// Search binary files as if they were text (`-a`)
+    pub text: bool,
+    /// Decompress gzip, zstd and bzip2 files before searching them (`-z`)
+    pub search_zip: bool,
+    /// Decode input from this encoding rather than reading it as UTF-8
+    pub encoding: Option<&'static Encoding>,
+    /// Print selected lines with every match replaced by this (`--replace`)
+    pub replace: Option<String>,
+    /// Write the replacements back to the files instead (`--in-place`)
+    pub in_place: bool,
+    /// Keep each edited file's original as `FILE.bak` (`--backup`)
+    pub backup: bool,
+    /// Search as the query is typed, in a terminal UI (`--interactive`)
+    pub interactive: bool,
+    /// Keep reading the file as it grows, like `tail -F` (`-F`)
+    pub follow: bool,
+    /// Let matches span lines, printing every line one covers (`-U`)
+    pub multiline: bool,
+    /// Build or update the index of each of these directories instead of
+    /// searching (`--build-index`)
+    pub build_index: Vec<String>,
+}
+
+/// When to highlight matches with ANSI colors (`--color`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ColorChoice {
+    /// Only when writing to a terminal and `NO_COLOR` isn't set
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    /// Decides for good, looking at stdout and the environment if needed.
+    pub fn enabled(self) -> bool {
+        match self {
+            Self::Always => true,
+            Self::Never => false,
+            Self::Auto => {
+                // https://no-color.org asks for any non-empty value to count
+                let no_color = env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty());
+                let dumb = env::var_os("TERM").is_some_and(|t| t == "dumb");
+                io::stdout().is_terminal() && !no_color && !dumb
+            }
+        }
+    }
+}
+
+impl FromStr for ColorChoice {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" | "tty" | "if-tty" => Ok(Self::Auto),
+            "always" | "yes" | "force" => Ok(Self::Always),
+            "never" | "no" | "none" => Ok(Self::Never),
+            _ => Err(()),
+        }
+    }
+}
+
+/// What `run` prints for each file.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OutputMode {
+    /// Every selected line (the default)
+    #[default]
+    Lines,
+    /// The number of selected lines (`-c`)
+    Count,
+    /// The names of files with at least one selected line (`-l`)
+    FilesWithMatches,
+    /// The names of files without any selected line (`-L`)
+    FilesWithoutMatch,
+    /// Nothing at all, only the exit status (`-q`)
+    Quiet,
+    /// One JSON object per line for each file, line and the run as a whole
+    /// (`--json`)
+    Json,
+}
+
+/// How a run went, which `main` turns into grep's exit status.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Matched,
+    NoMatch,
+    Failed,
+}
+
+impl Outcome {
+    pub fn exit_code(self) -> i32 {
+        match self {
+            Self::Matched => 0,
+            Self::NoMatch => 1,
+            Self::Failed => 2,
+        }
+    }
+}
+
+/// Reads one pattern per line from a `-f` file.
+fn read_patterns(path: &str) -> Result<Vec<String>, ArgsError> {
+    let contents = fs::read_to_string(path).map_err(|e| ArgsError::PatternFile {
+        path: path.to_string(),
+        reason: e.to_string(),
+    })?;
+
+    Ok(contents.lines().map(String::from).collect())
+}
+
+/// How many lines to show around each match (`-B`, `-A` and `-C`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Context {
+    pub before: usize,
+    pub after: usize,
+}
+
+/// Settings for a single `search_reader` pass over one input.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SearchOptions {
+    pub context: Context,
+    /// Select the lines that *don't* match (`-v`)
+    pub invert_match: bool,
+    /// Stop reading after this many selected lines (`-m`)
+    pub max_count: Option<usize>,
+}
+
+/// A line found by a search, and where it sits in its input.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Match<'a> {
+    /// 1-based, like editors and compilers count them
+    pub line_number: usize,
+    /// Offset of the start of the line from the start of the input
+    pub byte_offset: usize,
+    /// The line itself, without its line ending
+    pub line: &'a str,
+    /// Whether this is a context line shown around a match rather than a match
+    pub is_context: bool,
+    /// With `--fuzzy`, how many edits from the query the line's closest match is
+    pub distance: Option<usize>,
+    /// With `-U`, the parts of the line covered by matches, which may run on
+    /// from or into other lines; otherwise `None`, and they're found again
+    /// in the line when needed
+    pub spans: Option<&'a [Range<usize>]>,
 }
 
 impl Config {
-    pub fn build(mut args: impl Iterator<Item = String>) -> Result<Self, &'static str> {
+    /// Reads the command line, after the default flags from the config file
+    /// unless `--no-config` is given.
+    pub fn build(mut args: impl Iterator<Item = String>) -> Result<Self, ArgsError> {
         _ = args.next();
 
-        let query: String = match args.next() {
-            Some(arg) => arg,
-            None => return Err("Didn't get a query string"),
-        };
+        let args: Vec<String> = args.collect();
+        // Checked up front, since the config file's flags are parsed first
+        let settings = load_settings(&args, Settings::load)?;
 
-        let file_path: String = match args.next() {
-            Some(arg) => arg,
-            None => return Err("Didn't get a file path"),
-        };
+        Self::build_with(settings, args.into_iter())
+    }
+
+    /// Reads the command line, without its program name, after the flags
+    /// from `settings` and with its colors and ignore rules.
+    ///
+    /// Unlike `build`, this never reads a config file, so a program using
+    /// minigrep as a library gets the same results on every machine.
+    pub fn build_with(
+        settings: Settings,
+        args: impl Iterator<Item = String>,
+    ) -> Result<Self, ArgsError> {
+        let mut parser = Parser::new(settings.flags.into_iter().chain(args));
+        let mut positionals = Vec::new();
+        let mut patterns = Vec::new();
+        let mut patterns_given = false;
+        let mut ignore_case = env::var("IGNORE_CASE").is_ok();
+        let mut regex = false;
+        let mut word_regexp = false;
+        let mut line_regexp = false;
+        let mut fuzzy = None;
+        let mut recursive = false;
+        let mut hidden = false;
+        let mut no_ignore = false;
+        let mut include = Vec::new();
+        let mut exclude = Vec::new();
+        let mut line_number = false;
+        let mut byte_offset = false;
+        let mut context = Context::default();
+        let mut invert_match = false;
+        let mut max_count = None;
+        let mut output = OutputMode::Lines;
+        let mut color = ColorChoice::Auto;
+        let mut threads = thread::available_parallelism().map_or(1, NonZeroUsize::get);
+        let mut text = false;
+        let mut search_zip = false;
+        let mut encoding = None;
+        let mut replace = None;
+        let mut in_place = false;
+        let mut backup = false;
+        let mut interactive = false;
+        let mut follow = false;
+        let mut multiline = false;
+        let mut build_index = Vec::new();
 
-        let ignore_case = match args.next() {
-            Some(arg) => arg.starts_with("-i") || arg.starts_with("--ignore-case"),
-            None => env::var("IGNORE_CASE").is_ok(),
+        while let Some(arg) = parser.next_arg()? {
+            match arg {
+                Arg::Short('i') => ignore_case = true,
+                Arg::Short('E') => regex = true,
+                Arg::Short('e') => {
+                    patterns.push(parser.value()?);
+                    patterns_given = true;
+                }
+                Arg::Short('f') => {
+                    patterns.extend(read_patterns(&parser.value()?)?);
+                    patterns_given = true;
+                }
+                Arg::Short('w') => word_regexp = true,
+                Arg::Short('x') => line_regexp = true,
+                Arg::Short('r') => recursive = true,
+                Arg::Short('n') => line_number = true,
+                Arg::Short('b') => byte_offset = true,
+                Arg::Short('A') => context.after = parser.parse_value()?,
+                Arg::Short('B') => context.before = parser.parse_value()?,
+                Arg::Short('C') => {
+                    context.after = parser.parse_value()?;
+                    context.before = context.after;
+                }
+                Arg::Short('v') => invert_match = true,
+                Arg::Short('m') => max_count = Some(parser.parse_value()?),
+                Arg::Short('c') => output = OutputMode::Count,
+                Arg::Short('l') => output = OutputMode::FilesWithMatches,
+                Arg::Short('L') => output = OutputMode::FilesWithoutMatch,
+                Arg::Short('q') => output = OutputMode::Quiet,
+                Arg::Short('a') => text = true,
+                Arg::Short('z') => search_zip = true,
+                Arg::Short('F') => follow = true,
+                Arg::Short('U') => multiline = true,
+                Arg::Short('j') => threads = parser.parse_value::<NonZeroUsize>()?.get(),
+                Arg::Short('h') => return Err(ArgsError::Help),
+                Arg::Short('V') => return Err(ArgsError::Version),
+                Arg::Long(ref name) => match name.as_str() {
+                    "ignore-case" => ignore_case = true,
+                    "regex" => regex = true,
+                    "regexp" => {
+                        patterns.push(parser.value()?);
+                        patterns_given = true;
+                    }
+                    "file" => {
+                        patterns.extend(read_patterns(&parser.value()?)?);
+                        patterns_given = true;
+                    }
+                    "word-regexp" => word_regexp = true,
+                    "line-regexp" => line_regexp = true,
+                    "fuzzy" => fuzzy = Some(parser.parse_value()?),
+                    "recursive" => recursive = true,
+                    "hidden" => hidden = true,
+                    "no-ignore" => no_ignore = true,
+                    "include" => include.push(parser.value()?),
+                    "exclude" => exclude.push(parser.value()?),
+                    "line-number" => line_number = true,
+                    "byte-offset" => byte_offset = true,
+                    "after-context" => context.after = parser.parse_value()?,
+                    "before-context" => context.before = parser.parse_value()?,
+                    "context" => {
+                        context.after = parser.parse_value()?;
+                        context.before = context.after;
+                    }
+                    "invert-match" => invert_match = true,
+                    "max-count" => max_count = Some(parser.parse_value()?),
+                    "count" => output = OutputMode::Count,
+                    "files-with-matches" => output = OutputMode::FilesWithMatches,
+                    "files-without-match" => output = OutputMode::FilesWithoutMatch,
+                    "quiet" | "silent" => output = OutputMode::Quiet,
+                    "json" => output = OutputMode::Json,
+                    "text" => text = true,
+                    "search-zip" => search_zip = true,
+                    "encoding" => {
+                        let label = parser.value()?;
+                        let found = Encoding::for_label(label.trim().as_bytes());
+                        encoding = Some(found.ok_or_else(|| ArgsError::InvalidValue {
+                            option: String::from("--encoding"),
+                            value: label,
+                        })?);
+                    }
+                    "replace" => replace = Some(parser.value()?),
+                    "in-place" => in_place = true,
+                    "backup" => backup = true,
+                    "interactive" => interactive = true,
+                    "follow" => follow = true,
+                    "multiline" => multiline = true,
+                    "build-index" => build_index.push(parser.value()?),
+                    "threads" => threads = parser.parse_value::<NonZeroUsize>()?.get(),
+                    // Like grep, a bare --color means auto
+                    "color" | "colour" => {
+                        if parser.has_attached_value() {
+                            color = parser.parse_value()?;
+                        }
+                    }
+                    // Undo flags switched on by the config file
+                    "no-ignore-case" => ignore_case = false,
+                    "no-regex" => regex = false,
+                    "no-word-regexp" => word_regexp = false,
+                    "no-line-regexp" => line_regexp = false,
+                    "no-recursive" => recursive = false,
+                    "no-hidden" => hidden = false,
+                    "no-line-number" => line_number = false,
+                    "no-byte-offset" => byte_offset = false,
+                    "no-invert-match" => invert_match = false,
+                    "no-text" => text = false,
+                    "no-search-zip" => search_zip = false,
+                    "no-multiline" => multiline = false,
+                    "no-config" => {}
+                    "help" => return Err(ArgsError::Help),
+                    "version" => return Err(ArgsError::Version),
+                    _ => return Err(arg.unexpected()),
+                },
+                Arg::Positional(value) => positionals.push(value),
+                _ => return Err(arg.unexpected()),
+            }
+        }
+
+        // Indexing searches nothing, so there's nothing to search for or in
+        let indexing = !build_index.is_empty();
+        let search = [
+            (patterns_given || !positionals.is_empty(), "QUERY or PATH"),
+            (interactive, "--interactive"),
+            (follow, "--follow"),
+            (replace.is_some(), "--replace"),
+        ];
+        if let Some((_, with)) = search.iter().find(|(set, _)| indexing && *set) {
+            return Err(ArgsError::Conflict {
+                option: String::from("--build-index"),
+                with: String::from(*with),
+            });
+        }
+
+        let mut positionals = positionals.into_iter();
+        // With -e or -f the patterns are known, so every positional is a path,
+        // and the same goes for --interactive where the query is typed in
+        if !patterns_given && !interactive && !indexing {
+            patterns.push(positionals.next().ok_or(ArgsError::MissingQuery)?);
+        }
+        let file_paths: Vec<String> = positionals.collect();
+
+        if file_paths.is_empty() && !indexing {
+            return Err(ArgsError::MissingFilePath);
+        }
+
+        let globs = |option: &str, globs: Vec<String>| {
+            walk::build_globs(&globs).map_err(|e| ArgsError::InvalidValue {
+                option: option.to_string(),
+                value: e.glob().unwrap_or_default().to_string(),
+            })
         };
+        // Following never finishes one file to move on to the next
+        if follow && (recursive || file_paths.len() > 1) {
+            return Err(ArgsError::Conflict {
+                option: String::from("--follow"),
+                with: String::from("more than one PATH"),
+            });
+        }
+        // Following never finishes a file, so there's no total to print
+        let summaries = [
+            (OutputMode::Count, "--count"),
+            (OutputMode::FilesWithMatches, "--files-with-matches"),
+            (OutputMode::FilesWithoutMatch, "--files-without-match"),
+            (OutputMode::Quiet, "--quiet"),
+        ];
+        if let Some((_, with)) = summaries.iter().find(|(mode, _)| follow && output == *mode) {
+            return Err(ArgsError::Conflict {
+                option: String::from("--follow"),
+                with: String::from(*with),
+            });
+        }
+        // A file being followed is read as it grows, not decompressed
+        if follow && search_zip {
+            return Err(ArgsError::Conflict {
+                option: String::from("--follow"),
+                with: String::from("--search-zip"),
+            });
+        }
+        // These all go line by line
+        let single_line = [
+            (follow, "--follow"),
+            (replace.is_some(), "--replace"),
+            (line_regexp && !regex, "--line-regexp without --regex"),
+        ];
+        if let Some((_, with)) = single_line.iter().find(|(set, _)| multiline && *set) {
+            return Err(ArgsError::Conflict {
+                option: String::from("--multiline"),
+                with: String::from(*with),
+            });
+        }
+        // How close a match is says nothing about where its words or line end
+        let exact = [
+            (regex, "--regex"),
+            (word_regexp, "--word-regexp"),
+            (line_regexp, "--line-regexp"),
+        ];
+        if let Some((_, with)) = exact.iter().find(|(set, _)| fuzzy.is_some() && *set) {
+            return Err(ArgsError::Conflict {
+                option: String::from("--fuzzy"),
+                with: String::from(*with),
+            });
+        }
+        // The interactive search only lists the matching lines to pick from
+        let listing = [
+            (invert_match, "--invert-match"),
+            (output == OutputMode::Count, "--count"),
+            (
+                output == OutputMode::FilesWithMatches,
+                "--files-with-matches",
+            ),
+            (
+                output == OutputMode::FilesWithoutMatch,
+                "--files-without-match",
+            ),
+            (output == OutputMode::Json, "--json"),
+            (replace.is_some(), "--replace"),
+            (follow, "--follow"),
+        ];
+        if let Some((_, with)) = listing.iter().find(|(set, _)| interactive && *set) {
+            return Err(ArgsError::Conflict {
+                option: String::from("--interactive"),
+                with: String::from(*with),
+            });
+        }
+        if in_place && replace.is_none() {
+            return Err(ArgsError::Requires {
+                option: String::from("--in-place"),
+                needs: String::from("--replace"),
+            });
+        }
+        // Files are edited byte for byte, which only works for plain UTF-8
+        if in_place && (encoding.is_some() || search_zip) {
+            let with = if search_zip {
+                "--search-zip"
+            } else {
+                "--encoding"
+            };
+            return Err(ArgsError::Conflict {
+                option: String::from("--in-place"),
+                with: String::from(with),
+            });
+        }
+
+        let include = globs("--include", include)?;
+        let exclude = globs("--exclude", exclude)?;
 
         Ok(Self {
-            query,
-            file_path,
+            patterns,
+            file_paths,
             ignore_case,
+            regex,
+            word_regexp,
+            line_regexp,
+            fuzzy,
+            recursive,
+            hidden,
+            no_ignore,
+            include,
+            exclude,
+            ignore: settings.ignore,
+            line_number,
+            byte_offset,
+            context,
+            invert_match,
+            max_count,
+            output,
+            color,
+            colors: settings.colors,
+            threads,
+            text,
+            search_zip,
+            encoding,
+            replace,
+            in_place,
+            backup,
+            interactive,
+            follow,
+            multiline,
+            build_index,
         })
     }
+
+    /// The per-file search settings needed to produce `self.output`.
+    pub fn search_options(&self) -> SearchOptions {
+        let mut options = SearchOptions {
+            context: self.context,
+            invert_match: self.invert_match,
+            max_count: self.max_count,
+        };
+
+        if !matches!(self.output, OutputMode::Lines | OutputMode::Json) {
+            options.context = Context::default();
+        }
+        // A single selected line is enough to decide these
+        if matches!(
+            self.output,
+            OutputMode::FilesWithMatches | OutputMode::FilesWithoutMatch | OutputMode::Quiet
+        ) {
+            options.max_count = Some(1);
+        }
+
+        options
+    }
 }
 
-pub fn run(config: Config) -> Result<(), Box<dyn Error>> {
-    let contents = fs::read_to_string(config.file_path)?;
+pub fn run(mut config: Config) -> Result<Outcome, Box<dyn Error>> {
+    if config.interactive {
+        return tui::run(&config);
+    }
+    // Only here is it known that the output goes to stdout
+    config.color = if config.color.enabled() {
+        ColorChoice::Always
+    } else {
+        ColorChoice::Never
+    };
+    run_to(config, io::stdout().lock())
+}
 
-    let results = if config.ignore_case {
-        search_case_insensitive(&config.query, &contents)
+/// Like `run`, but writes the results to `out` rather than stdout.
+///
+/// `out` could be anything, so matches are only colored with
+/// `ColorChoice::Always`; `run` settles `Auto` by looking at stdout first.
+pub fn run_to<W: Write>(config: Config, out: W) -> Result<Outcome, Box<dyn Error>> {
+    let start = Instant::now();
+    let matcher = QueryMatcher::new(&config)?;
+    // JSON output reports the same spans that colors would highlight
+    let json = config.output == OutputMode::Json;
+    let highlight = (json || config.color == ColorChoice::Always).then_some(&matcher);
+    let mut printer = Printer::new(out, &config, highlight);
+    let mut tally = Tally::default();
+
+    let searched = search_files(&config, &matcher, &mut printer, &mut tally).and_then(|()| {
+        if json {
+            printer.print_summary(&tally, start.elapsed())
+        } else {
+            Ok(())
+        }
+    });
+    match searched {
+        Ok(()) => {}
+        // The reader went away (e.g. `minigrep ... | head`), nothing left to do
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => {}
+        Err(e) => return Err(e.into()),
+    }
+
+    // As with grep, -q only cares whether something matched
+    let outcome = if tally.had_errors && !(tally.matched && config.output == OutputMode::Quiet) {
+        Outcome::Failed
+    } else if tally.matched {
+        Outcome::Matched
     } else {
-        search(&config.query, &contents)
+        Outcome::NoMatch
     };
 
-    for line in results {
-        println!("{line}");
+    Ok(outcome)
+}
+
+/// What has turned up so far across all the files searched.
+#[derive(Debug, Default)]
+struct Tally {
+    matched: bool,
+    had_errors: bool,
+    files_searched: usize,
+    files_matched: usize,
+    lines_selected: usize,
+    errors: usize,
+}
+
+impl Tally {
+    /// Notes how one file went, reporting it if it couldn't be searched.
+    /// Only an error writing the results is passed on, since that ends the run.
+    fn record(&mut self, result: io::Result<usize>) -> io::Result<()> {
+        match result {
+            Ok(selected) => {
+                self.matched |= selected > 0;
+                self.files_searched += 1;
+                self.files_matched += usize::from(selected > 0);
+                self.lines_selected += selected;
+            }
+            Err(e) if e.kind() == io::ErrorKind::BrokenPipe => return Err(e),
+            Err(e) => {
+                eprintln!("minigrep: {e}");
+                self.had_errors = true;
+                self.errors += 1;
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether there's no point searching any more files.
+    fn is_done(&self, config: &Config) -> bool {
+        self.matched && config.output == OutputMode::Quiet
+    }
+}
+
+fn search_files<W: Write>(
+    config: &Config,
+    matcher: &QueryMatcher,
+    printer: &mut Printer<W>,
+    tally: &mut Tally,
+) -> io::Result<()> {
+    let mut files = collect_files(config);
+    // Like grep, only name the file when the output could come from several
+    let show_names = config.recursive || files.len() > 1;
+    if let Some(mut filter) = IndexFilter::new(config) {
+        files.retain(|file| {
+            file.as_ref()
+                .map_or(true, |file| filter.may_match(&file.path))
+        });
+    }
+
+    if config.threads > 1 && files.len() > 1 {
+        return pool::search_parallel(config, matcher, &files, show_names, printer, tally);
+    }
+
+    for file in files {
+        let result = file.and_then(|file| search_file(config, matcher, &file, show_names, printer));
+        tally.record(result)?;
+        if tally.is_done(config) {
+            break;
+        }
     }
 
     Ok(())
 }
 
-pub fn search<'a>(query: &str, contents: &'a str) -> Vec<&'a str> {
+/// Searches a single file and prints whatever `config.output` asks for,
+/// returning how many lines were selected.
+fn search_file<W: Write>(
+    config: &Config,
+    matcher: &QueryMatcher,
+    file: &FileEntry,
+    show_names: bool,
+    printer: &mut Printer<W>,
+) -> io::Result<usize> {
+    let name = display_name(&file.path);
+    if let (true, Some(replacement)) = (config.in_place, &config.replace) {
+        // Edits are reported by the files changing, not on stdout
+        return edit_file(config, matcher, file, replacement)
+            .map_err(|e| io::Error::new(e.kind(), format!("{name}: {e}")));
+    }
+
+    let mut sink = PrintSink {
+        config,
+        matcher,
+        printer,
+        prefix: show_names.then(|| name.clone()),
+        name: name.clone(),
+        binary: false,
+        binary_matched: false,
+    };
+    // Keep the kind, so a closed stdout can still be told apart
+    search_source(config, matcher, file, &mut sink)
+        .map_err(|e| io::Error::new(e.kind(), format!("{name}: {e}")))
+}
+
+fn edit_file(
+    config: &Config,
+    matcher: &QueryMatcher,
+    file: &FileEntry,
+    replacement: &str,
+) -> io::Result<usize> {
+    if file.path == Path::new(STDIN_PATH) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "can't edit standard input in place",
+        ));
+    }
+    replace::replace_in_place(config, matcher, &file.path, replacement)
+}
+
+/// Opens and searches a file, handing what it finds to `sink`.
+///
+/// Files with a NUL byte are treated as binary unless `--text` is given: a
+/// walked one is skipped without a word, and any other is left to the sink.
+fn search_source<W: Write>(
+    config: &Config,
+    matcher: &QueryMatcher,
+    file: &FileEntry,
+    sink: &mut PrintSink<W>,
+) -> io::Result<usize> {
+    let searcher = Searcher::new(config);
+    if config.follow && file.path != Path::new(STDIN_PATH) {
+        let tail = follow::Tail::open(&file.path)?;
+        return follow::search_following(&searcher, matcher, &file.path, tail, sink);
+    }
+    let mut input = searcher.open_input(&file.path)?;
+
+    sink.binary = !config.text && input.peek()?.contains(&0);
+    if sink.binary && file.walked {
+        return Ok(0);
+    }
+
+    sink.begin(&file.path)?;
+    let selected = searcher.search_input(matcher, input, sink)?;
+    sink.finish(&file.path, selected)?;
+
+    Ok(selected)
+}
+
+/// Prints one file's results the way the command line asked for.
+///
+/// Binary files only get "Binary file ... matches" instead of their lines,
+/// whose contents would be garbage. JSON escapes what it needs to, so there
+/// binary lines are reported like any other.
+struct PrintSink<'a, 'm, W: Write> {
+    config: &'a Config,
+    matcher: &'a QueryMatcher,
+    printer: &'a mut Printer<'m, W>,
+    name: String,
+    /// The name in front of each line, when searching several files
+    prefix: Option<String>,
+    binary: bool,
+    binary_matched: bool,
+}
+
+impl<W: Write> PrintSink<'_, '_, W> {
+    fn print(&mut self, m: &Match) -> io::Result<bool> {
+        // Context lines are shown as they are
+        let replaced = match &self.config.replace {
+            Some(replacement) if !m.is_context => {
+                Some(self.matcher.replace_all(m.line, replacement))
+            }
+            _ => None,
+        };
+        let m = match &replaced {
+            Some(line) => Match { line, ..m.clone() },
+            None => m.clone(),
+        };
+        match self.config.output {
+            OutputMode::Lines => {}
+            OutputMode::Json => return self.printer.print_json(&self.name, &m).map(|()| true),
+            _ => return Ok(true),
+        }
+        // A NUL further in makes the rest of the file binary too
+        self.binary = self.binary || (!self.config.text && m.line.contains('\0'));
+        if !self.binary {
+            self.printer.print(self.prefix.as_deref(), &m)?;
+            return Ok(true);
+        }
+        // One match is all it takes to say so
+        self.binary_matched = !m.is_context;
+        Ok(!self.binary_matched)
+    }
+}
+
+impl<W: Write> Sink for PrintSink<'_, '_, W> {
+    fn begin(&mut self, _path: &Path) -> io::Result<()> {
+        self.printer.begin_file(&self.name)
+    }
+
+    fn matched(&mut self, m: &Match) -> io::Result<bool> {
+        self.print(m)
+    }
+
+    fn context(&mut self, m: &Match) -> io::Result<bool> {
+        self.print(m)
+    }
+
+    fn finish(&mut self, _path: &Path, selected: usize) -> io::Result<()> {
+        if self.binary_matched {
+            self.printer.print_binary_match(&self.name)?;
+        }
+
+        let name = &self.name;
+        match self.config.output {
+            OutputMode::Count => self.printer.print_count(self.prefix.as_deref(), selected),
+            OutputMode::FilesWithMatches if selected > 0 => self.printer.print_name(name),
+            OutputMode::FilesWithoutMatch if selected == 0 => self.printer.print_name(name),
+            OutputMode::Json => self.printer.print_end(name, selected),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// The path that stands for standard input, as with most Unix tools.
+pub const STDIN_PATH: &str = "-";
+
+fn display_name(path: &Path) -> String {
+    if path == Path::new(STDIN_PATH) {
+        String::from("(standard input)")
+    } else {
+        path.display().to_string()
+    }
+}
+
+/// Streams `reader` line by line, handing every selected line to `on_line`
+/// as soon as it is read, so input of any size is searched in constant memory.
+/// `on_line` returns whether to carry on reading.
+///
+/// Lines that aren't valid UTF-8 are still searched, with the invalid bytes
+/// replaced by `�`; byte offsets always count the bytes actually read.
+///
+/// Up to `context.before` lines are held back and reported just ahead of the
+/// next selected line, and `context.after` lines are reported after each one.
+/// Returns how many lines were selected.
+pub fn search_reader<M: Matcher + ?Sized, R: BufRead>(
+    matcher: &M,
+    reader: R,
+    options: &SearchOptions,
+    on_line: impl FnMut(Match) -> io::Result<bool>,
+) -> io::Result<usize> {
+    select_lines(
+        reader,
+        options,
+        |_, line| matcher.is_match(line),
+        |line| matcher.distance(line),
+        on_line,
+    )
+}
+
+/// The line loop behind `search_reader`, with whether each line matches
+/// decided by `is_match`, given the line number and the line.
+fn select_lines<R: BufRead>(
+    mut reader: R,
+    options: &SearchOptions,
+    mut is_match: impl FnMut(usize, &str) -> bool,
+    distance: impl Fn(&str) -> Option<usize>,
+    mut on_line: impl FnMut(Match) -> io::Result<bool>,
+) -> io::Result<usize> {
+    let context = options.context;
+    let max_count = options.max_count.unwrap_or(usize::MAX);
+    let mut buf = Vec::new();
+    let mut held_back: VecDeque<(usize, usize, String)> = VecDeque::new();
+    let mut after_left = 0;
+    let mut selected = 0;
+    let mut line_number = 0;
+    let mut byte_offset = 0;
+
+    while selected < max_count || after_left > 0 {
+        buf.clear();
+        let read = reader.read_until(b'\n', &mut buf)?;
+        if read == 0 {
+            break;
+        }
+        line_number += 1;
+        let line_start = byte_offset;
+        byte_offset += read;
+
+        // Only borrows, unless the line has invalid UTF-8 to replace
+        let line = String::from_utf8_lossy(&buf);
+        let line = trim_line_ending(&line);
+        // Once the limit is hit, only the trailing context is left to print
+        let is_selected =
+            selected < max_count && is_match(line_number, line) != options.invert_match;
+
+        if is_selected {
+            for (line_number, byte_offset, line) in held_back.drain(..) {
+                let more = on_line(Match {
+                    line_number,
+                    byte_offset,
+                    line: &line,
+                    is_context: true,
+                    distance: None,
+                    spans: None,
+                })?;
+                if !more {
+                    return Ok(selected);
+                }
+            }
+            selected += 1;
+            after_left = context.after;
+            let more = on_line(Match {
+                line_number,
+                byte_offset: line_start,
+                line,
+                is_context: false,
+                distance: distance(line),
+                spans: None,
+            })?;
+            if !more {
+                break;
+            }
+        } else if after_left > 0 {
+            after_left -= 1;
+            let more = on_line(Match {
+                line_number,
+                byte_offset: line_start,
+                line,
+                is_context: true,
+                distance: None,
+                spans: None,
+            })?;
+            if !more {
+                break;
+            }
+        } else if context.before > 0 {
+            // Reuse the oldest line's buffer rather than allocating a new one
+            let mut kept = if held_back.len() == context.before {
+                held_back.pop_front().map(|(_, _, s)| s).unwrap_or_default()
+            } else {
+                String::new()
+            };
+            kept.clear();
+            kept.push_str(line);
+            held_back.push_back((line_number, line_start, kept));
+        }
+    }
+
+    Ok(selected)
+}
+
+/// The short options that take a value, as the value's own argument or
+/// attached (`-e PATTERN`, `-A3`).
+const SHORTS_WITH_VALUES: &[char] = &['e', 'f', 'A', 'B', 'C', 'm', 'j'];
+
+/// The long options that take a value, as the next argument or after `=`.
+const LONGS_WITH_VALUES: &[&str] = &[
+    "regexp",
+    "file",
+    "fuzzy",
+    "include",
+    "exclude",
+    "after-context",
+    "before-context",
+    "context",
+    "max-count",
+    "encoding",
+    "replace",
+    "threads",
+    "build-index",
+];
+
+/// The config file's settings, unless `args` says `--no-config`. A config
+/// file that can't be read or parsed doesn't stop `--help` or `--version`.
+///
+/// The options are found with the same [`Parser`] as everything else, so
+/// `-e --no-config` searches for `--no-config`, and `-ih` asks for help.
+fn load_settings(
+    args: &[String],
+    load: impl FnOnce() -> Result<Settings, ArgsError>,
+) -> Result<Settings, ArgsError> {
+    let mut parser = Parser::new(args.iter().cloned());
+    let mut informational = false;
+    // Mistakes are left for the full parse to report
+    while let Some(arg) = parser.next_arg().transpose() {
+        match arg {
+            Ok(Arg::Short('h' | 'V')) => informational = true,
+            Ok(Arg::Short(c)) if SHORTS_WITH_VALUES.contains(&c) => _ = parser.value(),
+            Ok(Arg::Long(name)) => match name.as_str() {
+                "no-config" => return Ok(Settings::default()),
+                "help" | "version" => informational = true,
+                _ if LONGS_WITH_VALUES.contains(&name.as_str()) || parser.has_attached_value() => {
+                    _ = parser.value()
+                }
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+
+    load().or_else(|e| {
+        if informational {
+            Ok(Settings::default())
+        } else {
+            Err(e)
+        }
+    })
+}
+
+/// Finds every matching line in `contents`, along with where it was found.
+pub fn search_lines<'a, M: Matcher + ?Sized>(matcher: &M, contents: &'a str) -> Vec<Match<'a>> {
+    let mut byte_offset = 0;
+
     contents
-        .lines()
-        .filter(|line| line.contains(query))
+        .split_inclusive('\n')
+        .enumerate()
+        .filter_map(|(i, raw)| {
+            let line_start = byte_offset;
+            byte_offset += raw.len();
+
+            let line = trim_line_ending(raw);
+            matcher.is_match(line).then(|| Match {
+                line_number: i + 1,
+                byte_offset: line_start,
+                line,
+                is_context: false,
+                distance: matcher.distance(line),
+                spans: None,
+            })
+        })
         .collect()
 }
 
-pub fn search_case_insensitive<'a>(query: &str, contents: &'a str) -> Vec<&'a str> {
-    contents
-        .lines()
-        .filter(|line| line.to_lowercase().contains(&query.to_lowercase()))
+pub fn search_with<'a, M: Matcher + ?Sized>(matcher: &M, contents: &'a str) -> Vec<&'a str> {
+    search_lines(matcher, contents)
+        .into_iter()
+        .map(|m| m.line)
         .collect()
 }
 
+fn trim_line_ending(line: &str) -> &str {
+    let line = line.strip_suffix('\n').unwrap_or(line);
+    line.strip_suffix('\r').unwrap_or(line)
+}
+
+pub fn search<'a>(query: &str, contents: &'a str) -> Vec<&'a str> {
+    search_with(query, contents)
+}
+
+pub fn search_case_insensitive<'a>(query: &str, contents: &'a str) -> Vec<&'a str> {
+    search_with(&FoldedQuery::new(query), contents)
+}
+
+/// Lines with a substring at most `max_distance` edits away from `query`.
+pub fn search_fuzzy<'a>(query: &str, max_distance: usize, contents: &'a str) -> Vec<&'a str> {
+    search_with(&FuzzyQuery::new(query, max_distance, false), contents)
+}
+
+pub fn search_regex<'a>(re: &Regex, contents: &'a str) -> Vec<&'a str> {
+    search_with(re, contents)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    use flate2::write::GzEncoder;
+
+    use crate::testutil::{build_config, TempDir};
+
     #[test]
     fn case_sensitive() {
         let expected = vec!["safe, fast, productive."];
@@ -104,7 +1132,7 @@ Trust me.";
             "poem.txt".to_string(),
             "-i".to_string(),
         ];
-        let config = Config::build(args.into_iter());
+        let config = build_config(args);
         let result = config.unwrap().ignore_case; // unwrap from Ok()
 
         assert_eq!(expected, result);
@@ -115,7 +1143,7 @@ Trust me.";
             "poem.txt".to_string(),
             "--ignore-case".to_string(),
         ];
-        let config = Config::build(args.into_iter());
+        let config = build_config(args);
         let result = config.unwrap().ignore_case; // unwrap from Ok()
 
         assert_eq!(expected, result);
@@ -127,9 +1155,9 @@ Trust me.";
             "target/debug/minigrep".to_string(),
             "to".to_string(),
             "poem.txt".to_string(),
-            "-a".to_string(),
+            "-E".to_string(),
         ];
-        let config = Config::build(args.into_iter());
+        let config = build_config(args);
         let result = config.unwrap().ignore_case; // unwrap from Ok()
 
         assert_eq!(expected, result);
@@ -138,12 +1166,268 @@ Trust me.";
             "target/debug/minigrep".to_string(),
             "to".to_string(),
             "poem.txt".to_string(),
-            "--abc".to_string(),
+            "--".to_string(),
+            "--ignore-case".to_string(),
         ];
-        let config = Config::build(args.into_iter());
-        let result = config.unwrap().ignore_case; // unwrap from Ok()
+        let config = build_config(args).unwrap();
 
-        assert_eq!(expected, result);
+        assert_eq!(expected, config.ignore_case);
+        assert_eq!(vec!["poem.txt", "--ignore-case"], config.file_paths);
+    }
+    #[test]
+    fn unknown_flags_are_errors() {
+        for flag in ["-k", "--abc", "-iy", "--ignore-cases"] {
+            let args = vec![
+                "target/debug/minigrep".to_string(),
+                "to".to_string(),
+                "poem.txt".to_string(),
+                flag.to_string(),
+            ];
+            assert!(matches!(
+                build_config(args),
+                Err(ArgsError::UnknownOption(_))
+            ));
+        }
+    }
+    #[test]
+    fn flags_in_any_position() {
+        let args = vec![
+            "target/debug/minigrep".to_string(),
+            "-iE".to_string(),
+            "to".to_string(),
+            "--recursive".to_string(),
+            "poem.txt".to_string(),
+        ];
+        let config = build_config(args).unwrap();
+
+        assert_eq!(vec!["to"], config.patterns);
+        assert_eq!(vec!["poem.txt"], config.file_paths);
+        assert!(config.ignore_case && config.regex && config.recursive);
+    }
+    #[test]
+    fn in_place_needs_a_replacement() {
+        let build = |flags: &[&str]| {
+            let args = ["minigrep"].iter().chain(flags).chain(&["x", "poem.txt"]);
+            build_config(args)
+        };
+        assert!(matches!(
+            build(&["--in-place"]),
+            Err(ArgsError::Requires { .. })
+        ));
+        assert!(matches!(
+            build(&["--in-place", "--replace=y", "--encoding=latin1"]),
+            Err(ArgsError::Conflict { .. })
+        ));
+        assert!(build(&["--in-place", "--replace", "y"]).unwrap().in_place);
+    }
+    #[test]
+    fn replacements_are_previewed() {
+        let args = ["minigrep", "-E", "--replace=<$0>", "-n", "-A1", "t[a-z]+"];
+        let output = search_temp_file("preview", b"a tea\nno\ntime to go\n", &args[1..]);
+        assert_eq!("1:a <tea>\n2-no\n3:<time> <to> go\n", output);
+    }
+    #[test]
+    fn fuzzy_search() {
+        let contents = "\
+Rust:
+safe, fast, productive.
+Pick three.
+Trust me.";
+        assert_eq!(
+            vec!["safe, fast, productive."],
+            search_fuzzy("prodcutive", 2, contents)
+        );
+        assert_eq!(vec!["Pick three."], search_fuzzy("thee", 1, contents));
+        assert!(search_fuzzy("prodcutive", 1, contents).is_empty());
+    }
+    #[test]
+    fn fuzzy_lines_are_annotated_with_their_distance() {
+        let output = search_temp_file(
+            "fuzzy",
+            b"recieve\nreceive\nreceipt\n",
+            &["--fuzzy=2", "-n", "receive"],
+        );
+        assert_eq!("1:~2:recieve\n2:~0:receive\n3:~2:receipt\n", output);
+    }
+    #[test]
+    fn interactive_only_lists_matching_lines() {
+        for flag in ["-v", "-c", "-l", "-L", "--json", "--replace=x", "-F"] {
+            let args = ["minigrep", "--interactive", flag, "poem.txt"];
+            assert!(
+                matches!(build_config(args), Err(ArgsError::Conflict { .. })),
+                "with {flag}"
+            );
+        }
+    }
+    #[test]
+    fn fuzzy_only_takes_plain_queries() {
+        for flag in ["-E", "-w", "-x"] {
+            let args = ["minigrep", "--fuzzy=0", flag, "to", "poem.txt"];
+            assert!(
+                matches!(build_config(args), Err(ArgsError::Conflict { .. })),
+                "with {flag}"
+            );
+        }
+        let args = [
+            "minigrep",
+            "--fuzzy=0",
+            "-x",
+            "--no-line-regexp",
+            "to",
+            "poem.txt",
+        ];
+        assert_eq!(Some(0), build_config(args).unwrap().fuzzy);
+    }
+    #[test]
+    fn run_writes_to_any_output() {
+        let args = ["minigrep", "-n", "--color=never", "frog", "poem.txt"];
+        let config = build_config(args).unwrap();
+
+        let mut out = Vec::new();
+        let outcome = run_to(config, &mut out).unwrap();
+
+        assert_eq!(Outcome::Matched, outcome);
+        assert_eq!(
+            "7:How public, like a frog\n",
+            String::from_utf8(out).unwrap()
+        );
+    }
+    #[test]
+    fn matches_across_lines_are_shown_on_every_line() {
+        let dir = TempDir::new("multiline-spans");
+        let path = dir.join("input");
+        fs::write(&path, "one two\nthree four\n").unwrap();
+        let output = |flag: &str| {
+            let args = ["minigrep", "-U", flag, "two\nthree"].map(String::from);
+            let args = args.into_iter().chain([path.display().to_string()]);
+            let mut out = Vec::new();
+            run_to(build_config(args).unwrap(), &mut out).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+
+        assert_eq!(
+            "one \x1b[1;31mtwo\x1b[0m\n\x1b[1;31mthree\x1b[0m four\n",
+            output("--color=always")
+        );
+        let json = output("--json");
+        assert!(json.contains(r#""submatches":[{"text":"two","start":4,"end":7}]"#));
+        assert!(json.contains(r#""submatches":[{"text":"three","start":0,"end":5}]"#));
+    }
+    #[test]
+    fn run_to_only_colors_when_told_to_always() {
+        let output = |color: &str| {
+            let args = ["minigrep", color, "frog", "poem.txt"];
+            let mut out = Vec::new();
+            run_to(build_config(args).unwrap(), &mut out).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        // Whether or not the tests' own stdout is a terminal
+        assert_eq!("How public, like a frog\n", output("--color=auto"));
+        assert_eq!(
+            "How public, like a \x1b[1;31mfrog\x1b[0m\n",
+            output("--color=always")
+        );
+    }
+    #[test]
+    fn flags_from_the_config_file_can_be_undone() {
+        let settings = Settings {
+            flags: ["-i", "-n", "--hidden"].map(String::from).to_vec(),
+            ..Settings::default()
+        };
+        let args = ["--no-ignore-case", "--no-line-number", "x", "poem.txt"];
+        let config = Config::build_with(settings, args.map(String::from).into_iter()).unwrap();
+        assert!(!config.ignore_case && !config.line_number);
+        assert!(config.hidden);
+    }
+    #[test]
+    fn broken_config_file_still_allows_help() {
+        let broken = || {
+            Err(ArgsError::ConfigFile {
+                path: String::from("config.toml"),
+                reason: String::from("bad"),
+            })
+        };
+        let args = |args: &[&str]| args.iter().map(|a| a.to_string()).collect::<Vec<_>>();
+        assert!(load_settings(&args(&["--help"]), broken).is_ok());
+        assert!(load_settings(&args(&["-V"]), broken).is_ok());
+        assert!(load_settings(&args(&["--no-config", "x"]), broken).is_ok());
+        assert!(load_settings(&args(&["x", "--", "--help"]), broken).is_err());
+        // Only options count, not the values that other options take
+        assert!(load_settings(&args(&["-ih", "x"]), broken).is_ok());
+        assert!(load_settings(&args(&["-e", "--no-config", "x"]), broken).is_err());
+        assert!(load_settings(&args(&["--regexp=-V", "x"]), broken).is_err());
+        assert!(load_settings(&args(&["-A", "2", "--no-config", "x"]), broken).is_ok());
+    }
+    #[test]
+    fn options_with_values_match_the_usage_text() {
+        let (mut shorts, mut longs) = (Vec::new(), Vec::new());
+        for line in args::USAGE.lines().map(str::trim_start) {
+            let Some((option, rest)) = line.split_once("--") else {
+                continue;
+            };
+            let name = rest.split(['[', ' ']).next().unwrap();
+            if !line.starts_with('-') || !rest[name.len()..].starts_with(" <") {
+                continue;
+            }
+            longs.push(name);
+            if let Some(short) = option.strip_prefix('-') {
+                shorts.extend(short.chars().next());
+            }
+        }
+        shorts.sort_unstable();
+        longs.sort_unstable();
+        let mut expected_shorts = SHORTS_WITH_VALUES.to_vec();
+        expected_shorts.sort_unstable();
+        let mut expected_longs = LONGS_WITH_VALUES.to_vec();
+        expected_longs.sort_unstable();
+        assert_eq!(expected_shorts, shorts);
+        assert_eq!(expected_longs, longs);
+    }
+    #[test]
+    fn follow_takes_a_single_path() {
+        let build = |args: &[&str]| build_config(args);
+        assert!(build(&["minigrep", "-F", "x", "app.log"]).unwrap().follow);
+        assert!(matches!(
+            build(&["minigrep", "--follow", "x", "a.log", "b.log"]),
+            Err(ArgsError::Conflict { .. })
+        ));
+        assert!(matches!(
+            build(&["minigrep", "-F", "-z", "x", "app.log.gz"]),
+            Err(ArgsError::Conflict { .. })
+        ));
+        for flag in ["-c", "-l", "-L", "-q"] {
+            assert!(
+                matches!(
+                    build(&["minigrep", "-F", flag, "x", "app.log"]),
+                    Err(ArgsError::Conflict { .. })
+                ),
+                "with {flag}"
+            );
+        }
+    }
+    #[test]
+    fn build_index_takes_only_directories() {
+        let config = build_config(["minigrep", "--build-index", "src", "--build-index=tests"]);
+        assert_eq!(vec!["src", "tests"], config.unwrap().build_index);
+        for args in [
+            &["minigrep", "--build-index", "src", "index"][..],
+            &["minigrep", "--build-index", "src", "-e", "index"],
+            &["minigrep", "--build-index", "src", "--interactive"],
+        ] {
+            assert!(
+                matches!(build_config(args), Err(ArgsError::Conflict { .. })),
+                "{args:?}"
+            );
+        }
+    }
+    #[test]
+    fn help_and_version_stop_parsing() {
+        for (flag, expected) in [("--help", ArgsError::Help), ("-V", ArgsError::Version)] {
+            let args = vec!["target/debug/minigrep".to_string(), flag.to_string()];
+            let err = build_config(args).err().unwrap();
+            assert!(err.is_informational());
+            assert_eq!(expected, err);
+        }
     }
     #[test]
     fn flag_false_when_none() {
@@ -153,8 +1437,406 @@ Trust me.";
             "to".to_string(),
             "poem.txt".to_string(),
         ];
-        let config = Config::build(args.into_iter());
+        let config = build_config(args);
         let result = config.unwrap().ignore_case; // uwwrap from Ok()
         assert_eq!(expected, result);
     }
+    #[test]
+    fn regex_anchors_classes_and_alternation() {
+        let contents = "\
+Rust:
+safe, fast, productive.
+Pick three.
+Trust me.";
+
+        let re = Regex::new(r"^[A-Z]\w+:$").unwrap();
+        assert_eq!(vec!["Rust:"], search_regex(&re, contents));
+
+        let re = Regex::new(r"three|me\.$").unwrap();
+        assert_eq!(
+            vec!["Pick three.", "Trust me."],
+            search_regex(&re, contents)
+        );
+    }
+    #[test]
+    fn regex_flag_builds_case_insensitive_matcher() {
+        let args = vec![
+            "target/debug/minigrep".to_string(),
+            "^r".to_string(),
+            "poem.txt".to_string(),
+            "--regex".to_string(),
+            "-i".to_string(),
+        ];
+        let config = build_config(args).unwrap();
+        assert!(config.regex);
+
+        let matcher = QueryMatcher::new(&config).unwrap();
+        assert_eq!(vec!["Rust:"], search_with(&matcher, "Rust:\nTrust me."));
+    }
+    #[test]
+    fn many_paths_and_recursive_flag() {
+        let args = vec![
+            "target/debug/minigrep".to_string(),
+            "to".to_string(),
+            "poem.txt".to_string(),
+            "-r".to_string(),
+            "src".to_string(),
+        ];
+        let config = build_config(args).unwrap();
+        assert_eq!(vec!["poem.txt", "src"], config.file_paths);
+        assert!(config.recursive);
+    }
+    #[test]
+    fn missing_file_path_is_an_error() {
+        let args = vec![
+            "target/debug/minigrep".to_string(),
+            "to".to_string(),
+            "-i".to_string(),
+        ];
+        assert_eq!(Some(ArgsError::MissingFilePath), build_config(args).err());
+    }
+    #[test]
+    fn reader_search_streams_matches_in_order() {
+        let contents = "Rust:\r\nsafe, fast, productive.\nPick three.\nTrust me.";
+        let matcher = QueryMatcher::Literal(String::from("st"));
+
+        let mut found = Vec::new();
+        search_reader(
+            &matcher,
+            contents.as_bytes(),
+            &SearchOptions::default(),
+            |m| {
+                found.push(m.line.to_string());
+                Ok(true)
+            },
+        )
+        .unwrap();
+
+        assert_eq!(vec!["Rust:", "safe, fast, productive.", "Trust me."], found);
+        assert_eq!(found, search("st", contents));
+    }
+    #[test]
+    fn reader_search_tolerates_invalid_utf8() {
+        let contents = b"caf\xe9 latte\nplain tea\n\xff\xfe tea again";
+        let matcher = QueryMatcher::Literal(String::from("tea"));
+
+        let mut found = Vec::new();
+        search_reader(&matcher, &contents[..], &SearchOptions::default(), |m| {
+            found.push((m.line.to_string(), m.byte_offset));
+            Ok(true)
+        })
+        .unwrap();
+
+        let expected = [("plain tea", 11), ("\u{fffd}\u{fffd} tea again", 21)];
+        assert_eq!(expected.map(|(l, o)| (l.to_string(), o)), found[..]);
+    }
+    fn search_temp_file(name: &str, contents: &[u8], flags: &[&str]) -> String {
+        let dir = TempDir::new(name);
+        let path = dir.join("input");
+        fs::write(&path, contents).unwrap();
+
+        let args = ["minigrep"]
+            .iter()
+            .chain(flags)
+            .map(|a| a.to_string())
+            .chain([path.display().to_string()]);
+        let config = build_config(args).unwrap();
+        let matcher = QueryMatcher::new(&config).unwrap();
+        let mut printer = Printer::new(Vec::new(), &config, None);
+        let file = FileEntry {
+            path: path.clone(),
+            walked: false,
+        };
+        search_file(&config, &matcher, &file, false, &mut printer).unwrap();
+
+        String::from_utf8(printer.into_inner())
+            .unwrap()
+            .replace(&path.display().to_string(), "FILE")
+    }
+    #[test]
+    fn binary_files_only_say_they_match() {
+        let contents = b"text line\nbin\0ary match\nanother match\n";
+        assert_eq!(
+            "Binary file FILE matches\n",
+            search_temp_file("binary", contents, &["match"])
+        );
+        assert_eq!("", search_temp_file("binary", contents, &["nothing"]));
+        assert_eq!(
+            "2\n",
+            search_temp_file("binary", contents, &["-c", "match"])
+        );
+        assert_eq!(
+            "bin\0ary match\nanother match\n",
+            search_temp_file("binary", contents, &["--text", "match"])
+        );
+
+        // Lines before the first NUL are still text
+        let contents = b"a match\n".repeat(2000).into_iter().chain(*b"\0 match\n");
+        let output = search_temp_file("late-binary", &contents.collect::<Vec<_>>(), &["match"]);
+        assert!(output.starts_with("a match\n"));
+        assert!(output.ends_with("a match\nBinary file FILE matches\n"));
+    }
+    #[test]
+    fn compressed_files_read_like_plain_text() {
+        let mut gzip = GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gzip.write_all(b"Nobody\nsomebody\nanybody\n").unwrap();
+        let gzip = gzip.finish().unwrap();
+
+        assert_eq!(
+            "2:somebody\n3:anybody\n",
+            search_temp_file("zip.gz", &gzip, &["-z", "-n", "[ey]body", "-E"])
+        );
+        // Without -z it's just binary
+        assert_eq!("", search_temp_file("zip.gz", &gzip, &["-n", "body"]));
+
+        // Text that starts like a compressed file but isn't one is still searched
+        let contents = b"BZh91AY&SY starts a bzip2 file\n";
+        assert_eq!(
+            "1:BZh91AY&SY starts a bzip2 file\n",
+            search_temp_file("not-zip", contents, &["-z", "-n", "bzip2"])
+        );
+        // and so is one that's only named like one
+        let dir = TempDir::new("plain-gz");
+        let path = dir.join("plain.gz");
+        fs::write(&path, "hello\n").unwrap();
+        let args = ["minigrep", "-z", "hello"].map(String::from);
+        let config = build_config(args.into_iter().chain([path.display().to_string()]));
+        let mut out = Vec::new();
+        assert_eq!(Outcome::Matched, run_to(config.unwrap(), &mut out).unwrap());
+        assert_eq!("hello\n", String::from_utf8(out).unwrap());
+    }
+    #[test]
+    fn other_encodings_are_decoded() {
+        let utf16: Vec<u8> = "Grüße\nAdiós\n"
+            .encode_utf16()
+            .flat_map(u16::to_le_bytes)
+            .collect();
+        assert_eq!(
+            "Adiós\n",
+            search_temp_file("utf16", &utf16, &["--encoding=utf-16le", "ó"])
+        );
+
+        let latin1 = b"Gr\xfc\xdfe\nAdi\xf3s\n";
+        assert_eq!(
+            "Grüße\n",
+            search_temp_file("latin1", latin1, &["--encoding", "latin1", "-i", "GRÜSSE"])
+        );
+
+        let args = ["minigrep", "--encoding=klingon", "x", "poem.txt"];
+        assert!(matches!(
+            build_config(args),
+            Err(ArgsError::InvalidValue { .. })
+        ));
+    }
+    #[test]
+    fn matches_know_their_position() {
+        let contents = "Rust:\r\nsafe, fast, productive.\nPick three.\nTrust me.";
+        let matcher = QueryMatcher::Literal(String::from("st"));
+
+        let found: Vec<(usize, usize)> = search_lines(&matcher, contents)
+            .iter()
+            .map(|m| (m.line_number, m.byte_offset))
+            .collect();
+        assert_eq!(vec![(1, 0), (2, 7), (4, 43)], found);
+    }
+    #[test]
+    fn reader_search_reports_context_lines() {
+        let contents = "a\nb\nmatch\nc\nd\ne\nf\nmatch\ng";
+        let matcher = QueryMatcher::Literal(String::from("match"));
+        let options = SearchOptions {
+            context: Context {
+                before: 2,
+                after: 1,
+            },
+            ..SearchOptions::default()
+        };
+
+        let mut found = Vec::new();
+        search_reader(&matcher, contents.as_bytes(), &options, |m| {
+            found.push((m.line_number, m.is_context));
+            Ok(true)
+        })
+        .unwrap();
+
+        let expected = vec![
+            (1, true),
+            (2, true),
+            (3, false),
+            (4, true),
+            (6, true),
+            (7, true),
+            (8, false),
+            (9, true),
+        ];
+        assert_eq!(expected, found);
+    }
+    #[test]
+    fn reader_search_inverts_and_stops_at_max_count() {
+        let contents = "a\nmatch\nb\nc\nmatch";
+        let matcher = QueryMatcher::Literal(String::from("match"));
+        let options = SearchOptions {
+            invert_match: true,
+            max_count: Some(2),
+            context: Context {
+                before: 0,
+                after: 1,
+            },
+        };
+
+        let mut found = Vec::new();
+        let selected = search_reader(&matcher, contents.as_bytes(), &options, |m| {
+            found.push((m.line.to_string(), m.is_context));
+            Ok(true)
+        })
+        .unwrap();
+
+        // The line after the last selected one is still shown as context
+        assert_eq!(2, selected);
+        let expected = [("a", false), ("match", true), ("b", false), ("c", true)];
+        assert_eq!(
+            expected.map(|(line, is_context)| (line.to_string(), is_context)),
+            found[..]
+        );
+    }
+    #[test]
+    fn output_mode_flags() {
+        let cases = [
+            ("-c", OutputMode::Count),
+            ("--files-with-matches", OutputMode::FilesWithMatches),
+            ("-L", OutputMode::FilesWithoutMatch),
+            ("--silent", OutputMode::Quiet),
+        ];
+        for (flag, expected) in cases {
+            let args = ["minigrep", "-C2", flag, "to", "poem.txt"];
+            let config = build_config(args).unwrap();
+            assert_eq!(expected, config.output);
+            // Context is only shown next to printed lines
+            assert_eq!(Context::default(), config.search_options().context);
+        }
+
+        let args = ["minigrep", "-vm3", "to", "poem.txt"];
+        let config = build_config(args).unwrap();
+        assert_eq!(
+            SearchOptions {
+                context: Context::default(),
+                invert_match: true,
+                max_count: Some(3),
+            },
+            config.search_options()
+        );
+    }
+    #[test]
+    fn patterns_from_flags_and_files() {
+        let dir = TempDir::new("patterns");
+        let path = dir.join("patterns.txt");
+        fs::write(&path, "frog\r\nbog\n").unwrap();
+
+        let args = vec![
+            "minigrep".to_string(),
+            "-e".to_string(),
+            "nobody".to_string(),
+            "poem.txt".to_string(),
+            format!("--file={}", path.display()),
+            "--regexp=-dash".to_string(),
+        ];
+        let config = build_config(args).unwrap();
+        assert_eq!(vec!["nobody", "frog", "bog", "-dash"], config.patterns);
+        // The first positional is a path once -e or -f has been given
+        assert_eq!(vec!["poem.txt"], config.file_paths);
+
+        fs::remove_file(&path).unwrap();
+        let args = vec![
+            "minigrep".to_string(),
+            "-f".to_string(),
+            path.display().to_string(),
+            "poem.txt".to_string(),
+        ];
+        assert!(matches!(
+            build_config(args),
+            Err(ArgsError::PatternFile { .. })
+        ));
+    }
+    #[test]
+    fn thread_count_must_be_positive() {
+        let args = ["minigrep", "-j3", "to", "poem.txt"];
+        let config = build_config(args).unwrap();
+        assert_eq!(3, config.threads);
+
+        let args = ["minigrep", "--threads=0", "to", "poem.txt"];
+        assert!(matches!(
+            build_config(args),
+            Err(ArgsError::InvalidValue { .. })
+        ));
+    }
+    #[test]
+    fn bad_globs_are_errors() {
+        let args = ["minigrep", "--include=a[", "to", "poem.txt"];
+        assert_eq!(
+            Some(ArgsError::InvalidValue {
+                option: String::from("--include"),
+                value: String::from("a["),
+            }),
+            build_config(args).err()
+        );
+    }
+    #[test]
+    fn color_flag_values() {
+        let cases = [
+            ("--color", ColorChoice::Auto),
+            ("--color=always", ColorChoice::Always),
+            ("--colour=never", ColorChoice::Never),
+        ];
+        for (flag, expected) in cases {
+            let args = ["minigrep", flag, "to", "poem.txt"];
+            let config = build_config(args).unwrap();
+            assert_eq!(expected, config.color);
+            assert_eq!(vec!["poem.txt"], config.file_paths);
+        }
+
+        let args = ["minigrep", "--color=sometimes", "to", "poem.txt"];
+        assert!(matches!(
+            build_config(args),
+            Err(ArgsError::InvalidValue { .. })
+        ));
+    }
+    #[test]
+    fn context_flags_take_numbers() {
+        let args = ["minigrep", "-n", "--context=2", "-A", "4", "to", "poem.txt"];
+        let config = build_config(args).unwrap();
+        assert!(config.line_number && !config.byte_offset);
+        assert_eq!(
+            Context {
+                before: 2,
+                after: 4
+            },
+            config.context
+        );
+
+        let args = ["minigrep", "to", "poem.txt", "-B"];
+        assert_eq!(
+            Some(ArgsError::MissingValue(String::from("-B"))),
+            build_config(args).err()
+        );
+    }
+    #[test]
+    fn dash_is_a_path_not_a_flag() {
+        let args = vec![
+            "target/debug/minigrep".to_string(),
+            "to".to_string(),
+            "-".to_string(),
+        ];
+        let config = build_config(args).unwrap();
+        assert_eq!(vec![STDIN_PATH], config.file_paths);
+    }
+    #[test]
+    fn invalid_regex_is_an_error() {
+        let args = vec![
+            "target/debug/minigrep".to_string(),
+            "(unclosed".to_string(),
+            "poem.txt".to_string(),
+            "-E".to_string(),
+        ];
+        let config = build_config(args).unwrap();
+        assert!(QueryMatcher::new(&config).is_err());
+    }
 }
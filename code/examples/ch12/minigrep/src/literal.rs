@@ -0,0 +1,181 @@
+use std::fs::File;
+use std::io::{self, Read};
+use std::ops::Deref;
+use std::path::Path;
+
+use memchr::{memchr, memchr_iter, memmem, memrchr};
+use memmap2::Mmap;
+
+use crate::{trim_line_ending, Match, Matcher, STDIN_PATH};
+
+/// Files at least this big are mapped into memory rather than read, since
+/// below it setting up the mapping costs more than copying does.
+const MAP_MIN_LEN: u64 = 64 * 1024;
+
+/// The whole of a file, in memory.
+pub enum Contents {
+    Mapped(Mmap),
+    Read(Vec<u8>),
+}
+
+impl Contents {
+    /// Loads the file at `path`, or returns `None` if it's not a regular file
+    /// and so has to be read as a stream, like standard input or a pipe.
+    pub fn load(path: &Path) -> io::Result<Option<Self>> {
+        if path == Path::new(STDIN_PATH) {
+            return Ok(None);
+        }
+        let mut file = File::open(path)?;
+        let metadata = file.metadata()?;
+        if !metadata.is_file() {
+            return Ok(None);
+        }
+
+        if metadata.len() < MAP_MIN_LEN {
+            let mut contents = Vec::with_capacity(metadata.len() as usize);
+            file.read_to_end(&mut contents)?;
+            return Ok(Some(Self::Read(contents)));
+        }
+        // SAFETY: the map is only ever read. Another process truncating the
+        // file while it's being searched can still crash the search, which
+        // like other grep tools we accept for the speed
+        let map = unsafe { Mmap::map(&file)? };
+        Ok(Some(Self::Mapped(map)))
+    }
+}
+
+impl Deref for Contents {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            Self::Mapped(map) => map,
+            Self::Read(contents) => contents,
+        }
+    }
+}
+
+/// Whether looking for `needle` in raw bytes finds the same lines as looking
+/// for it in their decoded text. That only differs for text containing `�`,
+/// which decoding puts in place of invalid UTF-8, or spanning lines.
+pub fn is_searchable(needle: &str) -> bool {
+    !needle.is_empty() && !needle.contains(['\n', char::REPLACEMENT_CHARACTER])
+}
+
+/// Finds the lines of `haystack` that `matcher` selects, given that each has
+/// to contain `needle`, calling `on_line` with each like `search_reader`.
+///
+/// Rather than going line by line, this looks for `needle` across the whole
+/// buffer with a vectorized search, and only then finds the bounds of the
+/// line around each hit and checks it with `matcher`. Line numbers come from
+/// counting the newlines skipped over, which is just as fast.
+pub fn search_literal<M: Matcher + ?Sized>(
+    matcher: &M,
+    needle: &str,
+    haystack: &[u8],
+    max_count: Option<usize>,
+    mut on_line: impl FnMut(Match) -> io::Result<bool>,
+) -> io::Result<usize> {
+    let finder = memmem::Finder::new(needle);
+    let max_count = max_count.unwrap_or(usize::MAX);
+    let mut selected = 0;
+    // Where to look from next, always the start of a line
+    let mut from = 0;
+    // How many newlines there are before `counted`
+    let mut newlines = 0;
+    let mut counted = 0;
+
+    while selected < max_count {
+        let Some(found) = finder.find(&haystack[from..]) else {
+            break;
+        };
+        let hit = from + found;
+        let start = memrchr(b'\n', &haystack[from..hit]).map_or(from, |i| from + i + 1);
+        let end = memchr(b'\n', &haystack[hit..]).map_or(haystack.len(), |i| hit + i + 1);
+        newlines += memchr_iter(b'\n', &haystack[counted..start]).count();
+        counted = start;
+        from = end;
+
+        let line = String::from_utf8_lossy(&haystack[start..end]);
+        let line = trim_line_ending(&line);
+        // Containing the needle isn't always enough, e.g. with -w
+        if !matcher.is_match(line) {
+            continue;
+        }
+        selected += 1;
+        let more = on_line(Match {
+            line_number: newlines + 1,
+            byte_offset: start,
+            line,
+            is_context: false,
+            distance: matcher.distance(line),
+            spans: None,
+        })?;
+        if !more {
+            break;
+        }
+    }
+
+    Ok(selected)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs;
+
+    use crate::testutil::{build_config, TempDir};
+    use crate::{search_reader, QueryMatcher, SearchOptions};
+
+    /// Every line found, as `line_number:byte_offset:line`.
+    fn found(selected: &mut Vec<String>) -> impl FnMut(Match) -> io::Result<bool> + '_ {
+        |m| {
+            selected.push(format!("{}:{}:{}", m.line_number, m.byte_offset, m.line));
+            Ok(true)
+        }
+    }
+
+    #[test]
+    fn finds_the_same_lines_as_reading_line_by_line() {
+        let haystack = b"duct tape\r\n\nrust is safe, rust\nnot rusty\xff\xfe rust\n\nrust";
+        for flags in [&[][..], &["-w"], &["-m", "2"]] {
+            let args = ["minigrep"].iter().chain(flags).chain(&["rust", "-"]);
+            let config = build_config(args).unwrap();
+            let matcher = QueryMatcher::new(&config).unwrap();
+            let options = SearchOptions {
+                max_count: config.max_count,
+                ..SearchOptions::default()
+            };
+
+            let mut expected = Vec::new();
+            search_reader(&matcher, &haystack[..], &options, found(&mut expected)).unwrap();
+            let mut selected = Vec::new();
+            let count = search_literal(
+                &matcher,
+                "rust",
+                haystack,
+                config.max_count,
+                found(&mut selected),
+            )
+            .unwrap();
+            assert_eq!(expected, selected, "with {flags:?}");
+            assert_eq!(expected.len(), count);
+        }
+    }
+
+    #[test]
+    fn maps_only_large_files() {
+        let dir = TempDir::new("literal");
+        fs::write(dir.join("small"), "small").unwrap();
+        fs::write(dir.join("large"), vec![b'x'; MAP_MIN_LEN as usize]).unwrap();
+
+        let small = Contents::load(&dir.join("small")).unwrap().unwrap();
+        assert!(matches!(small, Contents::Read(_)));
+        assert_eq!(b"small", &small[..]);
+        let large = Contents::load(&dir.join("large")).unwrap().unwrap();
+        assert!(matches!(large, Contents::Mapped(_)));
+        assert_eq!(MAP_MIN_LEN as usize, large.len());
+        assert!(Contents::load(Path::new(STDIN_PATH)).unwrap().is_none());
+    }
+}
@@ -1,16 +1,59 @@
 use std::env;
+use std::fmt;
+use std::io::{self, Write};
+use std::path::Path;
 use std::process;
 
-use minigrep::Config;
+use minigrep::{Config, Index, Outcome};
 
 fn main() {
     let config = Config::build(env::args()).unwrap_or_else(|err| {
+        if err.is_informational() {
+            if let Err(e) = print_line(&err) {
+                eprintln!("Application error: {e}");
+                process::exit(Outcome::Failed.exit_code());
+            }
+            process::exit(0);
+        }
         eprintln!("Problem parsing arguments: {err}");
-        process::exit(1);
+        process::exit(Outcome::Failed.exit_code());
     });
 
-    if let Err(e) = minigrep::run(config) {
-        eprintln!("Application error: {e}");
-        process::exit(1);
+    if !config.build_index.is_empty() {
+        index(&config.build_index);
+    }
+
+    match minigrep::run(config) {
+        Ok(outcome) => process::exit(outcome.exit_code()),
+        Err(e) => {
+            eprintln!("Application error: {e}");
+            process::exit(Outcome::Failed.exit_code());
+        }
+    }
+}
+
+/// `minigrep --build-index <DIR>...`: builds or updates the index of each DIR.
+fn index(dirs: &[String]) -> ! {
+    for dir in dirs {
+        let indexed = Index::update(Path::new(dir)).and_then(|stats| {
+            print_line(format_args!(
+                "{dir}: {} files indexed, {} read",
+                stats.files, stats.updated
+            ))
+        });
+        if let Err(e) = indexed {
+            eprintln!("Application error: {dir}: {e}");
+            process::exit(Outcome::Failed.exit_code());
+        }
+    }
+    process::exit(0);
+}
+
+/// Writes `line` to stdout, which it's fine to find closed, as with
+/// `minigrep --help | head -2`.
+fn print_line(line: impl fmt::Display) -> io::Result<()> {
+    match writeln!(io::stdout(), "{line}") {
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+        written => written,
     }
 }
@@ -0,0 +1,436 @@
+use std::borrow::Cow;
+use std::cmp::Reverse;
+use std::error::Error;
+use std::ops::Range;
+
+use aho_corasick::{AhoCorasick, Input, MatchKind};
+use regex::{Regex, RegexBuilder};
+
+use crate::{Config, FoldedQuery, FoldedQuerySet, FuzzyQuery};
+
+/// Finds a query in a line of text.
+///
+/// Everything minigrep can search for implements this: a plain `str`, a
+/// [`Regex`], the case-folding and fuzzy queries, and [`QueryMatcher`], which
+/// is built from a [`Config`]. Only `find_at` has to be written; the rest
+/// follow from it.
+pub trait Matcher {
+    /// The first match starting at or after byte `from` of `line`, which may
+    /// be empty (e.g. for an empty query).
+    fn find_at(&self, line: &str, from: usize) -> Option<Range<usize>>;
+
+    fn is_match(&self, line: &str) -> bool {
+        self.find_at(line, 0).is_some()
+    }
+
+    /// The byte ranges of every non-overlapping match in `text`, left to
+    /// right, empty ones included.
+    ///
+    /// Matchers that have to prepare the text before searching it override
+    /// this to do so once, rather than on every call to `find_at`.
+    fn find_all(&self, text: &str) -> Vec<Range<usize>> {
+        find_each(self, text)
+    }
+
+    /// The byte ranges of every non-overlapping, non-empty match in `line`,
+    /// left to right.
+    fn find_spans(&self, line: &str) -> Vec<Range<usize>> {
+        let mut spans = self.find_all(line);
+        spans.retain(|span| !span.is_empty());
+        spans
+    }
+
+    /// How many edits from the query the closest match in `line` is, for
+    /// matchers that allow some; `None` for exact ones.
+    fn distance(&self, _line: &str) -> Option<usize> {
+        None
+    }
+
+    /// Text that every match contains exactly, if there is any, so a search
+    /// can look for it across a whole buffer before checking lines one by one.
+    fn literal(&self) -> Option<&str> {
+        None
+    }
+
+    /// `line` with every match swapped for `replacement`.
+    fn replace_all<'a>(&self, line: &'a str, replacement: &str) -> Cow<'a, str> {
+        replace_spans(line, self.find_spans(line), replacement)
+    }
+}
+
+impl Matcher for str {
+    fn find_at(&self, line: &str, from: usize) -> Option<Range<usize>> {
+        line[from..]
+            .find(self)
+            .map(|i| from + i..from + i + self.len())
+    }
+
+    fn is_match(&self, line: &str) -> bool {
+        line.contains(self)
+    }
+
+    fn literal(&self) -> Option<&str> {
+        Some(self)
+    }
+}
+
+impl Matcher for Regex {
+    fn find_at(&self, line: &str, from: usize) -> Option<Range<usize>> {
+        Regex::find_at(self, line, from).map(|m| m.range())
+    }
+
+    fn is_match(&self, line: &str) -> bool {
+        Regex::is_match(self, line)
+    }
+
+    /// Here `$1`, `${name}` and so on stand for capture groups, and `$$`
+    /// for a `$`.
+    fn replace_all<'a>(&self, line: &'a str, replacement: &str) -> Cow<'a, str> {
+        Regex::replace_all(self, line, replacement)
+    }
+}
+
+/// How the command line's query is matched against each line, built once
+/// per run.
+pub enum QueryMatcher {
+    Literal(String),
+    CaseInsensitive(FoldedQuery),
+    Regex(Regex),
+    /// Any of several literal patterns (`-e`/`-f`), found in one pass
+    Literals(AhoCorasick),
+    /// Any of several patterns under case folding
+    CaseInsensitiveSet(FoldedQuerySet),
+    /// Only the inner matcher's matches that are whole words (`-w`)
+    Words(Box<QueryMatcher>),
+    /// Only an inner match that covers the entire line (`-x`)
+    WholeLine(Box<QueryMatcher>),
+    /// Anything close enough to any of the patterns (`--fuzzy`)
+    Fuzzy(Vec<FuzzyQuery>),
+}
+
+impl QueryMatcher {
+    pub fn new(config: &Config) -> Result<Self, Box<dyn Error>> {
+        Self::with_patterns(config, &config.patterns)
+    }
+
+    /// Matches `patterns` instead of the config's own, with its other settings.
+    pub fn with_patterns(config: &Config, patterns: &[String]) -> Result<Self, Box<dyn Error>> {
+        // e.g. an empty -f file, which like grep matches nothing
+        if patterns.is_empty() {
+            return Ok(Self::Literals(AhoCorasick::new(patterns)?));
+        }
+
+        if config.regex {
+            let pattern = match patterns {
+                [pattern] => pattern.clone(),
+                _ => patterns
+                    .iter()
+                    .map(|p| format!("(?:{p})"))
+                    .collect::<Vec<_>>()
+                    .join("|"),
+            };
+            // Let the regex engine handle the bounds so it can backtrack
+            // into another alternative when the first one isn't a whole word
+            let pattern = if config.line_regexp {
+                format!("^(?:{pattern})$")
+            } else if config.word_regexp {
+                format!(r"\b{{start-half}}(?:{pattern})\b{{end-half}}")
+            } else {
+                pattern
+            };
+            // Across lines, ^ and $ still mean the start and end of one
+            let re = RegexBuilder::new(&pattern)
+                .case_insensitive(config.ignore_case)
+                .multi_line(config.multiline)
+                .build()?;
+            return Ok(Self::Regex(re));
+        }
+
+        if let Some(max_distance) = config.fuzzy {
+            let queries = patterns
+                .iter()
+                .map(|p| FuzzyQuery::new(p, max_distance, config.ignore_case));
+            return Ok(Self::Fuzzy(queries.collect()));
+        }
+
+        let matcher = match (patterns, config.ignore_case) {
+            ([pattern], false) => Self::Literal(pattern.clone()),
+            ([pattern], true) => Self::CaseInsensitive(FoldedQuery::new(pattern)),
+            (_, false) => Self::Literals(
+                AhoCorasick::builder()
+                    .match_kind(MatchKind::LeftmostLongest)
+                    .build(patterns)?,
+            ),
+            (_, true) => Self::CaseInsensitiveSet(FoldedQuerySet::new(patterns)?),
+        };
+
+        let matcher = if config.line_regexp {
+            Self::WholeLine(Box::new(matcher))
+        } else if config.word_regexp {
+            Self::Words(Box::new(matcher))
+        } else {
+            matcher
+        };
+
+        Ok(matcher)
+    }
+}
+
+impl Matcher for QueryMatcher {
+    fn find_at(&self, line: &str, from: usize) -> Option<Range<usize>> {
+        match self {
+            Self::Literal(query) => query.find_at(line, from),
+            Self::CaseInsensitive(query) => query.find_at(line, from),
+            Self::Regex(re) => Matcher::find_at(re, line, from),
+            Self::Literals(automaton) => automaton
+                .find(Input::new(line).span(from..line.len()))
+                .map(|m| m.range()),
+            Self::CaseInsensitiveSet(set) => set.find_at(line, from),
+            Self::Fuzzy(queries) => queries
+                .iter()
+                .filter_map(|q| q.find_at(line, from))
+                .min_by_key(|span| (span.start, Reverse(span.end))),
+            Self::Words(inner) => {
+                let mut from = from;
+                loop {
+                    let span = inner.find_at(line, from)?;
+                    if is_word_bounded(line, &span) {
+                        return Some(span);
+                    }
+                    // Try again one char further on, the next candidate may overlap
+                    from = span.start + line[span.start..].chars().next()?.len_utf8();
+                }
+            }
+            Self::WholeLine(inner) => inner
+                .find_at(line, 0)
+                .filter(|span| from == 0 && *span == (0..line.len())),
+        }
+    }
+
+    fn is_match(&self, line: &str) -> bool {
+        match self {
+            Self::Literal(query) => query.is_match(line),
+            Self::CaseInsensitive(query) => query.is_match(line),
+            Self::Regex(re) => Matcher::is_match(re, line),
+            Self::Literals(automaton) => automaton.is_match(line),
+            Self::CaseInsensitiveSet(set) => set.is_match(line),
+            Self::Fuzzy(queries) => queries.iter().any(|q| q.is_match(line)),
+            _ => self.find_at(line, 0).is_some(),
+        }
+    }
+
+    fn find_all(&self, text: &str) -> Vec<Range<usize>> {
+        match self {
+            Self::CaseInsensitiveSet(set) => set.find_all(text),
+            Self::Words(inner) => match &**inner {
+                Self::CaseInsensitiveSet(set) => {
+                    set.find_all_where(text, |span| is_word_bounded(text, span))
+                }
+                _ => find_each(self, text),
+            },
+            _ => find_each(self, text),
+        }
+    }
+
+    fn distance(&self, line: &str) -> Option<usize> {
+        match self {
+            Self::Fuzzy(queries) => queries.iter().filter_map(|q| q.distance(line)).min(),
+            _ => None,
+        }
+    }
+
+    fn literal(&self) -> Option<&str> {
+        match self {
+            Self::Literal(query) => Some(query),
+            Self::Words(inner) | Self::WholeLine(inner) => inner.literal(),
+            _ => None,
+        }
+    }
+
+    /// In regex mode `$1`, `${name}` and so on stand for capture groups, and
+    /// `$$` for a `$`.
+    fn replace_all<'a>(&self, line: &'a str, replacement: &str) -> Cow<'a, str> {
+        match self {
+            Self::Regex(re) => Matcher::replace_all(re, line, replacement),
+            _ => replace_spans(line, self.find_spans(line), replacement),
+        }
+    }
+}
+
+/// Every match in `text` by calling `find_at` from the end of the last one,
+/// which is what `find_all` does unless a matcher knows better.
+fn find_each<M: Matcher + ?Sized>(matcher: &M, text: &str) -> Vec<Range<usize>> {
+    let mut spans = Vec::new();
+    let mut from = 0;
+
+    while let Some(span) = matcher.find_at(text, from) {
+        from = span.end;
+        let is_empty = span.is_empty();
+        spans.push(span);
+        if is_empty {
+            // Step over a char, so an empty match isn't found again
+            match text[from..].chars().next() {
+                Some(c) => from += c.len_utf8(),
+                None => break,
+            }
+        }
+    }
+
+    spans
+}
+
+fn replace_spans<'a>(line: &'a str, spans: Vec<Range<usize>>, replacement: &str) -> Cow<'a, str> {
+    if spans.is_empty() {
+        return Cow::Borrowed(line);
+    }
+    let mut replaced = String::with_capacity(line.len());
+    let mut copied = 0;
+    for span in spans {
+        replaced.push_str(&line[copied..span.start]);
+        replaced.push_str(replacement);
+        copied = span.end;
+    }
+    replaced.push_str(&line[copied..]);
+    Cow::Owned(replaced)
+}
+
+/// Whether `span` has no word char right before or right after it, using the
+/// same Unicode-aware idea of a word char as regex's `\w`.
+fn is_word_bounded(line: &str, span: &Range<usize>) -> bool {
+    let is_word_char = |c: char| c.is_alphanumeric() || c == '_';
+    let before = line[..span.start].chars().next_back();
+    let after = line[span.end..].chars().next();
+
+    !before.is_some_and(is_word_char) && !after.is_some_and(is_word_char)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::testutil::build_config;
+
+    #[test]
+    fn spans_for_every_occurrence() {
+        let matcher = QueryMatcher::Literal(String::from("ab"));
+        assert_eq!(vec![0..2, 3..5], matcher.find_spans("ab ab a"));
+
+        let matcher = QueryMatcher::Regex(Regex::new("a*").unwrap());
+        assert_eq!(vec![1..3], matcher.find_spans("baab"));
+    }
+
+    fn build(args: &[&str]) -> QueryMatcher {
+        let args = ["minigrep"].iter().chain(args).chain(&["poem.txt"]);
+        let config = build_config(args).unwrap();
+        QueryMatcher::new(&config).unwrap()
+    }
+
+    #[test]
+    fn whole_words_only() {
+        let line = "tomorrow, Toad, to-do and To";
+        assert_eq!(vec![16..18], build(&["-w", "to"]).find_spans(line));
+        assert_eq!(vec![16..18, 26..28], build(&["-wi", "to"]).find_spans(line));
+        assert_eq!(vec![16..18], build(&["-wE", "t."]).find_spans(line));
+        assert!(!build(&["-w", "to"]).is_match("tomorrow"));
+
+        // The first candidate being part of a longer word mustn't hide a later one
+        assert_eq!(vec![4..6], build(&["-w", "aa"]).find_spans("aaa aa"));
+        assert_eq!(
+            vec![0..8],
+            build(&["-wE", "to|tomorrow"]).find_spans("tomorrow")
+        );
+
+        // Several patterns under -i find every whole word in one pass too
+        let matcher = build(&["-wi", "-e", "AA", "-e", "to"]);
+        let line = "aaa aa To toad";
+        assert_eq!(vec![4..6, 7..9], matcher.find_spans(line));
+        assert_eq!(find_each(&matcher, line), matcher.find_all(line));
+
+        // Unicode letters are word chars too
+        assert!(!build(&["-w", "stra"]).is_match("straße"));
+        assert!(build(&["-wi", "STRASSE"]).is_match("die straße."));
+    }
+
+    #[test]
+    fn whole_line_only() {
+        assert!(build(&["-x", "Pick three."]).is_match("Pick three."));
+        assert!(!build(&["-x", "Pick"]).is_match("Pick three."));
+        assert!(build(&["-xi", "PICK THREE."]).is_match("Pick three."));
+        assert!(build(&["-xE", "a|ab"]).is_match("ab"));
+        assert!(build(&["-x", ""]).is_match(""));
+        assert!(!build(&["-x", ""]).is_match("not empty"));
+    }
+
+    #[test]
+    fn any_of_several_patterns() {
+        let line = "To tell your name the livelong day";
+        let matcher = build(&["-e", "name", "-e", "tell", "-e", "tel"]);
+        assert_eq!(vec![3..7, 13..17], matcher.find_spans(line));
+
+        let matcher = build(&["-i", "-e", "TELL", "-e", "DAY"]);
+        assert_eq!(vec![3..7, 31..34], matcher.find_spans(line));
+
+        let matcher = build(&["-E", "-e", "^to", "-e", "da.$"]);
+        assert_eq!(vec![31..34], matcher.find_spans(line));
+
+        let matcher = build(&["-w", "-e", "live", "-e", "livelong"]);
+        assert_eq!(vec![22..30], matcher.find_spans(line));
+
+        let matcher = build(&[
+            "-x",
+            "-e",
+            "tell",
+            "-e",
+            "To tell your name the livelong day",
+        ]);
+        assert!(matcher.is_match(line));
+    }
+
+    #[test]
+    fn no_patterns_match_nothing() {
+        let config = Config {
+            patterns: Vec::new(),
+            ..build_config(["minigrep", "x", "poem.txt"].map(String::from)).unwrap()
+        };
+        assert!(!QueryMatcher::new(&config).unwrap().is_match("anything"));
+    }
+
+    #[test]
+    fn case_insensitive_spans_map_back_to_the_original() {
+        let matcher = QueryMatcher::CaseInsensitive(FoldedQuery::new("rust"));
+        assert_eq!(vec![0..4, 9..13], matcher.find_spans("Rust and RUST"));
+
+        // 'İ' folds to two chars, which must not shift the spans after it
+        let matcher = QueryMatcher::CaseInsensitive(FoldedQuery::new("x"));
+        assert_eq!(vec![2..3, 5..6], matcher.find_spans("İXİx"));
+    }
+
+    #[test]
+    fn replacements_expand_groups_only_in_regex_mode() {
+        let matcher = build(&["-E", r"(\w+)@(\w+)"]);
+        assert_eq!(
+            "b at a, d at c",
+            matcher.replace_all("a@b, c@d", "$2 at $1")
+        );
+
+        let matcher = build(&["-i", "to"]);
+        assert_eq!(
+            "$1 tell, $1morrow",
+            matcher.replace_all("To tell, tomorrow", "$1")
+        );
+        assert!(matches!(
+            matcher.replace_all("nothing", "x"),
+            Cow::Borrowed(_)
+        ));
+    }
+
+    #[test]
+    fn fuzzy_matches_report_their_distance() {
+        let matcher = build(&["--fuzzy=2", "-e", "recieve", "-e", "receipt"]);
+        assert_eq!(Some(0), matcher.distance("a receipt"));
+        assert_eq!(Some(2), matcher.distance("we receive"));
+        assert_eq!(vec![3..10], matcher.find_spans("we receive"));
+        assert!(!matcher.is_match("deceive"));
+        assert_eq!(None, build(&["recieve"]).distance("recieve"));
+    }
+}
@@ -0,0 +1,195 @@
+use std::borrow::Cow;
+use std::io;
+use std::ops::{Range, RangeInclusive};
+
+use memchr::memchr_iter;
+
+use crate::{select_lines, Match, Matcher, SearchOptions};
+
+/// Searches `contents` as a whole rather than line by line, so a match can
+/// span lines (`-U`), and selects every line that some match covers. Context,
+/// `-v` and `-m` then work on those lines just as `search_reader` does.
+///
+/// Each selected line comes with the parts of it the matches cover, as the
+/// line on its own may not match at all.
+pub fn search_multiline<M: Matcher + ?Sized>(
+    matcher: &M,
+    contents: &[u8],
+    options: &SearchOptions,
+    mut on_line: impl FnMut(Match) -> io::Result<bool>,
+) -> io::Result<usize> {
+    let text = String::from_utf8_lossy(contents);
+    // Every match in one go, so e.g. -i folds the text just once
+    let found = matcher.find_all(&text);
+    let covered = covered_lines(&found, &text);
+    let mut next = covered.iter().peekable();
+    let is_covered = |line_number, _: &str| {
+        while next.next_if(|lines| *lines.end() < line_number).is_some() {}
+        next.peek()
+            .is_some_and(|lines| lines.contains(&line_number))
+    };
+
+    // Replacing invalid UTF-8 moves the matches away from the bytes read,
+    // so then the printer looks for them in each line again
+    let mut spans = matches!(text, Cow::Borrowed(_)).then(|| LineSpans::new(&found));
+    let with_spans = |m: Match| match &mut spans {
+        Some(spans) if !m.is_context => {
+            let in_line = spans.in_line(m.byte_offset, m.line.len());
+            on_line(Match {
+                spans: Some(in_line),
+                ..m
+            })
+        }
+        _ => on_line(m),
+    };
+    // How far off a line is means nothing once a match can cover several
+    select_lines(contents, options, is_covered, |_| None, with_spans)
+}
+
+/// The 1-based numbers of the lines that each of the `matches` in `text`
+/// covers, in order, with overlapping runs of lines merged.
+fn covered_lines(matches: &[Range<usize>], text: &str) -> Vec<RangeInclusive<usize>> {
+    let mut covered: Vec<RangeInclusive<usize>> = Vec::new();
+    // Only the newlines since the last match need counting each time
+    let mut line = 1;
+    let mut counted = 0;
+    let mut line_at = |offset| {
+        line += memchr_iter(b'\n', &text.as_bytes()[counted..offset]).count();
+        counted = offset;
+        line
+    };
+
+    for span in matches {
+        let first = line_at(span.start);
+        // A match that takes in a newline ends on the line it ends
+        let last = line_at(span.end.saturating_sub(1).max(span.start));
+        match covered.last_mut() {
+            Some(lines) if first <= *lines.end() => *lines = *lines.start()..=last,
+            _ => covered.push(first..=last),
+        }
+    }
+
+    covered
+}
+
+/// Cuts the matches in the whole text down to the part of each that falls
+/// in a line, for lines asked about in order.
+struct LineSpans<'m> {
+    /// The matches that don't end before the last line asked about
+    matches: &'m [Range<usize>],
+    in_line: Vec<Range<usize>>,
+}
+
+impl<'m> LineSpans<'m> {
+    fn new(matches: &'m [Range<usize>]) -> Self {
+        Self {
+            matches,
+            in_line: Vec::new(),
+        }
+    }
+
+    /// The non-empty parts of the matches within the `len` bytes from
+    /// `start`, counted from `start`.
+    fn in_line(&mut self, start: usize, len: usize) -> &[Range<usize>] {
+        let end = start + len;
+        let passed = self
+            .matches
+            .iter()
+            .take_while(|span| span.end <= start)
+            .count();
+        self.matches = &self.matches[passed..];
+
+        self.in_line.clear();
+        let reaching = self.matches.iter().take_while(|span| span.start < end);
+        // Overlapping matches from several patterns needn't end in order
+        for span in reaching.filter(|span| span.end > start) {
+            let part = span.start.max(start) - start..span.end.min(end) - start;
+            if !part.is_empty() {
+                self.in_line.push(part);
+            }
+        }
+        &self.in_line
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::{Duration, Instant};
+
+    use crate::testutil::build_config;
+    use crate::QueryMatcher;
+
+    /// Every line reported, as `line_number:line`, with `-` after the number
+    /// for context lines.
+    fn search(flags: &[&str], contents: &str) -> Vec<String> {
+        let args = ["minigrep", "-U"].iter().chain(flags).chain(&["-"]);
+        let config = build_config(args).unwrap();
+        let matcher = QueryMatcher::new(&config).unwrap();
+
+        let mut found = Vec::new();
+        search_multiline(
+            &matcher,
+            contents.as_bytes(),
+            &config.search_options(),
+            |m| {
+                let separator = if m.is_context { '-' } else { ':' };
+                found.push(format!("{}{separator}{}", m.line_number, m.line));
+                Ok(true)
+            },
+        )
+        .unwrap();
+        found
+    }
+
+    #[test]
+    fn matches_span_lines() {
+        let contents = "\
+fn main() {
+    println!(
+        \"hi\",
+    );
+}
+";
+        assert_eq!(
+            vec!["2:    println!(", "3:        \"hi\",", "4:    );"],
+            search(&["-E", r"println!\(\s*[^)]*\)"], contents)
+        );
+        // A literal newline works too, and a match ending in one stays on its line
+        assert_eq!(vec!["4:    );", "5:}"], search(&[");\n}"], contents));
+        assert_eq!(vec!["1:fn main() {"], search(&["{\n"], contents));
+        // With -E, ^ and $ still match at the start and end of each line
+        assert_eq!(vec!["4:    );"], search(&["-E", "^ +\\);$"], contents));
+    }
+
+    #[test]
+    fn context_and_invert_use_the_covered_lines() {
+        let contents = "a\nstart\nmiddle\nend\nb\nc\n";
+        assert_eq!(
+            vec!["1-a", "2:start", "3:middle", "4:end", "5-b"],
+            search(&["-C", "1", "-E", "(?s)start.*end"], contents)
+        );
+        assert_eq!(
+            vec!["1:a", "5:b", "6:c"],
+            search(&["-v", "-E", "(?s)start.*end"], contents)
+        );
+        assert_eq!(
+            vec!["2:start"],
+            search(&["-m", "1", "start\nmiddle"], contents)
+        );
+    }
+
+    #[test]
+    fn many_case_insensitive_matches_are_found_in_one_pass() {
+        let contents = "foo\npin\n".repeat(50_000);
+        // Searching on after each match used to fold the rest of the text again
+        let started = Instant::now();
+        let found = search(&["-i", "-e", "FOO", "-e", "pin"], &contents);
+        assert_eq!(100_000, found.len());
+        assert_eq!("2:pin", found[1]);
+        let found = search(&["-wi", "-e", "FOO", "-e", "pin"], &contents);
+        assert_eq!(100_000, found.len());
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+}
@@ -0,0 +1,180 @@
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+use std::mem;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, SendError, SyncSender};
+use std::thread;
+
+use crate::printer::Printer;
+use crate::{search_file, Config, FileEntry, QueryMatcher, Tally};
+
+/// How much output a worker gathers before handing it over.
+const PIECE_SIZE: usize = 64 * 1024;
+
+/// How many pieces of a file's output may wait for the files before it to be
+/// printed; after that its worker waits too.
+const PIECES_AHEAD: usize = 4;
+
+/// What a worker hands over while searching a file.
+enum Piece {
+    Output(Vec<u8>),
+    /// The end of the file's output, with the search's result, or `None`
+    /// when its path couldn't be listed
+    Done(Option<io::Result<usize>>),
+}
+
+/// Searches `files` on `config.threads` worker threads.
+///
+/// Each worker takes the next file off the list and passes its results on a
+/// piece at a time; they are printed here in the order the files were given,
+/// so the output is the same as searching one file at a time. The file that
+/// is up next is printed as it's searched, while the workers ahead of it
+/// only get to buffer a few pieces before they have to wait.
+pub fn search_parallel<W: Write>(
+    config: &Config,
+    matcher: &QueryMatcher,
+    files: &[io::Result<FileEntry>],
+    show_names: bool,
+    printer: &mut Printer<W>,
+    tally: &mut Tally,
+) -> io::Result<()> {
+    let next_file = AtomicUsize::new(0);
+    let stop = AtomicBool::new(false);
+    let template = printer.with_output(io::sink());
+    let (started, received) = mpsc::channel();
+
+    thread::scope(|scope| {
+        for _ in 0..config.threads.min(files.len()) {
+            let started = started.clone();
+            let (next_file, stop, template) = (&next_file, &stop, &template);
+
+            scope.spawn(move || {
+                while !stop.load(Ordering::Relaxed) {
+                    let i = next_file.fetch_add(1, Ordering::Relaxed);
+                    let Some(file) = files.get(i) else {
+                        break;
+                    };
+                    let (pieces, receiver) = mpsc::sync_channel(PIECES_AHEAD);
+                    if started.send((i, receiver)).is_err() {
+                        break;
+                    }
+
+                    // Paths that couldn't be listed are reported in order below
+                    let mut output = template.with_output(PieceWriter::new(pieces));
+                    let result = file
+                        .as_ref()
+                        .ok()
+                        .map(|path| search_file(config, matcher, path, show_names, &mut output));
+
+                    if output.into_inner().finish(result).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(started);
+
+        let printed = print_in_order(config, files, received, printer, tally);
+        // Let the workers wind down before leaving the scope; the receivers
+        // are gone, so any of them waiting to hand over a piece stops too
+        stop.store(true, Ordering::Relaxed);
+        printed
+    })
+}
+
+/// Prints each file's pieces as they come in, one file after the other,
+/// until every file is done or there's no point going on.
+fn print_in_order<W: Write>(
+    config: &Config,
+    files: &[io::Result<FileEntry>],
+    started: Receiver<(usize, Receiver<Piece>)>,
+    printer: &mut Printer<W>,
+    tally: &mut Tally,
+) -> io::Result<()> {
+    let mut waiting = BTreeMap::new();
+
+    for (next_to_print, file) in files.iter().enumerate() {
+        let pieces = loop {
+            if let Some(pieces) = waiting.remove(&next_to_print) {
+                break pieces;
+            }
+            // Only when a worker panicked, which the scope reports
+            let Ok((i, pieces)) = started.recv() else {
+                return Ok(());
+            };
+            waiting.insert(i, pieces);
+        };
+
+        let mut starts_file = true;
+        let result = loop {
+            match pieces.recv() {
+                Ok(Piece::Output(output)) => {
+                    printer.print_buffered(&output, starts_file)?;
+                    starts_file = false;
+                }
+                Ok(Piece::Done(result)) => break result,
+                Err(_) => return Ok(()),
+            }
+        };
+        let result = result.unwrap_or_else(|| {
+            let e = file
+                .as_ref()
+                .expect_err("only paths that failed to list go unsearched");
+            Err(io::Error::new(e.kind(), e.to_string()))
+        });
+
+        tally.record(result)?;
+        if tally.is_done(config) {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+/// Gathers a worker's output and sends it on in pieces of `PIECE_SIZE`.
+struct PieceWriter {
+    buffer: Vec<u8>,
+    pieces: SyncSender<Piece>,
+}
+
+impl PieceWriter {
+    fn new(pieces: SyncSender<Piece>) -> Self {
+        Self {
+            buffer: Vec::new(),
+            pieces,
+        }
+    }
+
+    /// Sends what's left of the output and then the search's result.
+    fn finish(mut self, result: Option<io::Result<usize>>) -> Result<(), SendError<Piece>> {
+        if !self.buffer.is_empty() {
+            self.pieces
+                .send(Piece::Output(mem::take(&mut self.buffer)))?;
+        }
+        self.pieces.send(Piece::Done(result))
+    }
+}
+
+impl Write for PieceWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        if self.buffer.len() >= PIECE_SIZE {
+            self.flush()?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        let piece = Piece::Output(mem::take(&mut self.buffer));
+        self.pieces.send(piece).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "the results are no longer printed",
+            )
+        })
+    }
+}
@@ -0,0 +1,401 @@
+use std::borrow::Cow;
+use std::fmt;
+use std::io::{self, Write};
+use std::ops::Range;
+use std::time::Duration;
+
+use serde::Serialize;
+
+use crate::{ColorScheme, Config, Match, Matcher, OutputMode, QueryMatcher, Tally};
+
+const RESET: &str = "\x1b[0m";
+
+/// Writes search results in grep's format: `path:line:offset:text` for
+/// matches, `-` instead of `:` for context lines, and `--` between groups of
+/// lines that aren't next to each other.
+///
+/// Given a matcher to highlight with, output is colored with the config's
+/// color scheme, except for matched text that `--replace` has changed.
+///
+/// With `--json` it writes [`Event`]s instead, one JSON object per line.
+pub struct Printer<'m, W: Write> {
+    out: W,
+    highlight: Option<&'m QueryMatcher>,
+    colors: Option<ColorScheme>,
+    json: bool,
+    line_number: bool,
+    byte_offset: bool,
+    separators: bool,
+    last_line: Option<usize>,
+    printed_any: bool,
+}
+
+impl<'m, W: Write> Printer<'m, W> {
+    pub fn new(out: W, config: &Config, highlight: Option<&'m QueryMatcher>) -> Self {
+        let json = config.output == OutputMode::Json;
+        Self {
+            out,
+            colors: (highlight.is_some() && !json).then(|| config.colors.clone()),
+            // Spans found in the original line don't fit the replaced one
+            highlight: highlight.filter(|_| config.replace.is_none()),
+            json,
+            line_number: config.line_number,
+            byte_offset: config.byte_offset,
+            separators: config.output == OutputMode::Lines
+                && (config.context.before > 0 || config.context.after > 0),
+            last_line: None,
+            printed_any: false,
+        }
+    }
+
+    /// A printer with the same settings that writes to `out` instead, e.g. to
+    /// buffer one file's results on a worker thread.
+    pub fn with_output<V: Write>(&self, out: V) -> Printer<'m, V> {
+        Printer {
+            out,
+            highlight: self.highlight,
+            colors: self.colors.clone(),
+            json: self.json,
+            line_number: self.line_number,
+            byte_offset: self.byte_offset,
+            separators: self.separators,
+            last_line: None,
+            printed_any: false,
+        }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+
+    /// Writes out a piece of the results another printer buffered for a
+    /// file, with a group separator in front of the file's first piece if
+    /// it follows earlier lines.
+    pub fn print_buffered(&mut self, output: &[u8], starts_file: bool) -> io::Result<()> {
+        if output.is_empty() {
+            return Ok(());
+        }
+        if self.separators && self.printed_any && starts_file {
+            writeln!(self.out, "--")?;
+        }
+        self.printed_any = true;
+        self.out.write_all(output)
+    }
+
+    /// Forgets where the previous file left off, so its line numbers don't
+    /// run into the next file's.
+    pub fn begin_file(&mut self, name: &str) -> io::Result<()> {
+        self.last_line = None;
+        if self.json {
+            self.write_event(&Event::Begin { path: name })?;
+        }
+        Ok(())
+    }
+
+    pub fn print(&mut self, name: Option<&str>, m: &Match) -> io::Result<()> {
+        if self.separators && self.printed_any {
+            let adjacent = self.last_line.is_some_and(|last| m.line_number == last + 1);
+            if !adjacent {
+                writeln!(self.out, "--")?;
+            }
+        }
+        self.last_line = Some(m.line_number);
+        self.printed_any = true;
+
+        let sep = if m.is_context { '-' } else { ':' };
+        let colors = self.colors.as_ref();
+        let code = |pick: fn(&ColorScheme) -> &str| colors.map_or("", pick);
+
+        if let Some(name) = name {
+            write_colored(&mut self.out, code(|c| &c.path), name)?;
+            write!(self.out, "{sep}")?;
+        }
+        if self.line_number {
+            write_colored(&mut self.out, code(|c| &c.line_number), m.line_number)?;
+            write!(self.out, "{sep}")?;
+        }
+        if self.byte_offset {
+            write!(self.out, "{}{sep}", m.byte_offset)?;
+        }
+        if let Some(distance) = m.distance {
+            write!(self.out, "~{distance}{sep}")?;
+        }
+        match (self.highlight, colors) {
+            // Context lines and inverted matches have nothing to highlight
+            (Some(matcher), Some(colors)) if !m.is_context => {
+                write_highlighted(&mut self.out, &colors.matched, m.line, &spans(matcher, m))
+            }
+            _ => writeln!(self.out, "{}", m.line),
+        }
+    }
+
+    /// Prints the number of selected lines in a file (`-c`).
+    pub fn print_count(&mut self, name: Option<&str>, count: usize) -> io::Result<()> {
+        match name {
+            Some(name) => writeln!(self.out, "{name}:{count}"),
+            None => writeln!(self.out, "{count}"),
+        }
+    }
+
+    /// Reports a selected or context line as a JSON event, with the span of
+    /// every match in it.
+    pub fn print_json(&mut self, name: &str, m: &Match) -> io::Result<()> {
+        let submatches = match self.highlight {
+            Some(matcher) if !m.is_context => spans(matcher, m)
+                .iter()
+                .map(|span| Submatch {
+                    text: &m.line[span.clone()],
+                    start: span.start,
+                    end: span.end,
+                })
+                .collect(),
+            _ => Vec::new(),
+        };
+        let line = Line {
+            path: name,
+            line_number: m.line_number,
+            byte_offset: m.byte_offset,
+            line: m.line,
+            submatches,
+            distance: m.distance,
+        };
+        if m.is_context {
+            self.write_event(&Event::Context(line))
+        } else {
+            self.write_event(&Event::Match(line))
+        }
+    }
+
+    /// Reports that a file is done, and how many lines it had selected.
+    pub fn print_end(&mut self, name: &str, selected: usize) -> io::Result<()> {
+        self.write_event(&Event::End {
+            path: name,
+            lines_selected: selected,
+        })
+    }
+
+    /// Reports the totals for the whole run, as the last JSON event.
+    pub fn print_summary(&mut self, tally: &Tally, elapsed: Duration) -> io::Result<()> {
+        self.write_event(&Event::Summary {
+            files_searched: tally.files_searched,
+            files_matched: tally.files_matched,
+            lines_selected: tally.lines_selected,
+            errors: tally.errors,
+            elapsed_secs: elapsed.as_secs_f64(),
+        })
+    }
+
+    fn write_event(&mut self, event: &Event) -> io::Result<()> {
+        serde_json::to_writer(&mut self.out, event)?;
+        writeln!(self.out)
+    }
+
+    /// Says that a binary file matched, in place of printing its lines.
+    pub fn print_binary_match(&mut self, name: &str) -> io::Result<()> {
+        writeln!(self.out, "Binary file {name} matches")
+    }
+
+    /// Prints just a file's name (`-l` and `-L`).
+    pub fn print_name(&mut self, name: &str) -> io::Result<()> {
+        writeln!(self.out, "{name}")
+    }
+}
+
+/// The matches in a selected line: those found along with it, as `-U`
+/// does, or else whatever `matcher` finds in the line on its own.
+fn spans<'s>(matcher: &QueryMatcher, m: &Match<'s>) -> Cow<'s, [Range<usize>]> {
+    match m.spans {
+        Some(spans) => Cow::Borrowed(spans),
+        None => Cow::Owned(matcher.find_spans(m.line)),
+    }
+}
+
+fn write_highlighted<W: Write>(
+    out: &mut W,
+    code: &str,
+    line: &str,
+    spans: &[Range<usize>],
+) -> io::Result<()> {
+    let mut printed = 0;
+    for span in spans {
+        write!(out, "{}", &line[printed..span.start])?;
+        write_colored(out, code, &line[span.clone()])?;
+        printed = span.end;
+    }
+    writeln!(out, "{}", &line[printed..])
+}
+
+/// Writes `text` wrapped in the SGR `code`, or as it is if that's empty.
+fn write_colored<W: Write>(out: &mut W, code: &str, text: impl fmt::Display) -> io::Result<()> {
+    if code.is_empty() {
+        write!(out, "{text}")
+    } else {
+        write!(out, "\x1b[{code}m{text}{RESET}")
+    }
+}
+
+/// What `--json` prints, tagged with its `type`.
+///
+/// Byte offsets count from the start of the input, and submatch spans from
+/// the start of the line.
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Event<'a> {
+    Begin {
+        path: &'a str,
+    },
+    Match(Line<'a>),
+    Context(Line<'a>),
+    End {
+        path: &'a str,
+        lines_selected: usize,
+    },
+    Summary {
+        files_searched: usize,
+        files_matched: usize,
+        lines_selected: usize,
+        errors: usize,
+        elapsed_secs: f64,
+    },
+}
+
+#[derive(Debug, Serialize)]
+struct Line<'a> {
+    path: &'a str,
+    line_number: usize,
+    byte_offset: usize,
+    line: &'a str,
+    /// Empty for context lines and lines selected by `-v`
+    submatches: Vec<Submatch<'a>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    distance: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+struct Submatch<'a> {
+    text: &'a str,
+    start: usize,
+    end: usize,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::testutil::build_config;
+
+    fn line(line_number: usize, is_context: bool) -> Match<'static> {
+        Match {
+            line_number,
+            byte_offset: line_number * 10,
+            line: "text",
+            is_context,
+            distance: None,
+            spans: None,
+        }
+    }
+
+    #[test]
+    fn prefixes_and_group_separators() {
+        let args = ["minigrep", "-nb", "-C1", "text", "a.txt"];
+        let config = build_config(args).unwrap();
+        let mut printer = Printer::new(Vec::new(), &config, None);
+
+        printer.print(Some("a.txt"), &line(1, true)).unwrap();
+        printer.print(Some("a.txt"), &line(2, false)).unwrap();
+        printer.print(Some("a.txt"), &line(5, false)).unwrap();
+        printer.begin_file("b.txt").unwrap();
+        printer.print(Some("b.txt"), &line(6, false)).unwrap();
+
+        let expected = "\
+a.txt-1-10-text
+a.txt:2:20:text
+--
+a.txt:5:50:text
+--
+b.txt:6:60:text
+";
+        assert_eq!(expected, String::from_utf8(printer.out).unwrap());
+    }
+
+    #[test]
+    fn highlights_every_match() {
+        let args = ["minigrep", "-i", "to", "a.txt"];
+        let config = build_config(args).unwrap();
+        let matcher = QueryMatcher::new(&config).unwrap();
+        let mut printer = Printer::new(Vec::new(), &config, Some(&matcher));
+
+        let mut m = Match {
+            line_number: 1,
+            byte_offset: 0,
+            line: "To tell, tomorrow",
+            is_context: false,
+            distance: None,
+            spans: None,
+        };
+        printer.print(None, &m).unwrap();
+        m.is_context = true;
+        printer.print(None, &m).unwrap();
+
+        let expected = "\
+\x1b[1;31mTo\x1b[0m tell, \x1b[1;31mto\x1b[0mmorrow
+To tell, tomorrow
+";
+        assert_eq!(expected, String::from_utf8(printer.out).unwrap());
+    }
+
+    #[test]
+    fn colors_come_from_the_scheme() {
+        let args = ["minigrep", "-n", "to", "a.txt"];
+        let mut config = build_config(args).unwrap();
+        config.colors = ColorScheme {
+            matched: String::from("32"),
+            path: String::from("35"),
+            line_number: String::new(),
+        };
+        let matcher = QueryMatcher::new(&config).unwrap();
+        let mut printer = Printer::new(Vec::new(), &config, Some(&matcher));
+
+        let m = Match {
+            line_number: 3,
+            byte_offset: 0,
+            line: "go to",
+            is_context: false,
+            distance: None,
+            spans: None,
+        };
+        printer.print(Some("a.txt"), &m).unwrap();
+
+        let expected = "\x1b[35ma.txt\x1b[0m:3:go \x1b[32mto\x1b[0m\n";
+        assert_eq!(expected, String::from_utf8(printer.out).unwrap());
+    }
+
+    #[test]
+    fn json_events() {
+        let args = ["minigrep", "--json", "-i", "to", "a.txt"];
+        let config = build_config(args).unwrap();
+        let matcher = QueryMatcher::new(&config).unwrap();
+        let mut printer = Printer::new(Vec::new(), &config, Some(&matcher));
+
+        let mut m = Match {
+            line_number: 2,
+            byte_offset: 7,
+            line: "To \"t\" too",
+            is_context: false,
+            distance: None,
+            spans: None,
+        };
+        printer.begin_file("a.txt").unwrap();
+        printer.print_json("a.txt", &m).unwrap();
+        m.is_context = true;
+        printer.print_json("a.txt", &m).unwrap();
+        printer.print_end("a.txt", 1).unwrap();
+
+        let expected = r#"{"type":"begin","path":"a.txt"}
+{"type":"match","path":"a.txt","line_number":2,"byte_offset":7,"line":"To \"t\" too","submatches":[{"text":"To","start":0,"end":2},{"text":"to","start":7,"end":9}]}
+{"type":"context","path":"a.txt","line_number":2,"byte_offset":7,"line":"To \"t\" too","submatches":[]}
+{"type":"end","path":"a.txt","lines_selected":1}
+"#;
+        assert_eq!(expected, String::from_utf8(printer.out).unwrap());
+    }
+}
@@ -0,0 +1,141 @@
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use crate::{search_reader, Config, Context, Matcher, QueryMatcher, SearchOptions};
+
+/// Applies `--replace` to every selected line of the file at `path` and
+/// writes the result back, returning how many lines were selected.
+///
+/// Everything outside the replaced matches is kept byte for byte, including
+/// line endings and any lines that aren't valid UTF-8, which are left alone.
+/// Binary files are not touched unless `--text` is given. A file with nothing
+/// to change is not rewritten at all.
+pub fn replace_in_place(
+    config: &Config,
+    matcher: &QueryMatcher,
+    path: &Path,
+    replacement: &str,
+) -> io::Result<usize> {
+    let contents = fs::read(path)?;
+    if !config.text && contents.contains(&0) {
+        return Ok(0);
+    }
+
+    let options = SearchOptions {
+        context: Context::default(),
+        invert_match: config.invert_match,
+        max_count: config.max_count,
+    };
+    let mut edited = Vec::with_capacity(contents.len());
+    let mut copied = 0;
+
+    let selected = search_reader(matcher, &contents[..], &options, |m| {
+        let end = m.byte_offset + m.line.len();
+        // A line searched lossily doesn't read the same as the bytes on disk
+        if contents.get(m.byte_offset..end) == Some(m.line.as_bytes()) {
+            edited.extend_from_slice(&contents[copied..m.byte_offset]);
+            edited.extend_from_slice(matcher.replace_all(m.line, replacement).as_bytes());
+            copied = end;
+        }
+        Ok(true)
+    })?;
+    edited.extend_from_slice(&contents[copied..]);
+
+    if edited != contents {
+        if config.backup {
+            fs::copy(path, backup_path(path))?;
+        }
+        write_atomically(path, &edited)?;
+    }
+
+    Ok(selected)
+}
+
+/// Where `--backup` keeps the original of `path`: the same name plus `.bak`.
+pub fn backup_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".bak");
+    PathBuf::from(name)
+}
+
+/// Replaces the file at `path` with `contents`, so that anyone reading it
+/// sees either the old file or the new one and never half of each.
+///
+/// The new contents go to a temporary file next to it, which is then renamed
+/// over the original; the original's permissions are kept, if there is one.
+pub fn write_atomically(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    let temp_path = path.with_file_name(format!(".{file_name}.minigrep-{}", std::process::id()));
+
+    let written = File::create(&temp_path).and_then(|mut temp| {
+        temp.write_all(contents)?;
+        match fs::metadata(path) {
+            Ok(original) => temp.set_permissions(original.permissions())?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        temp.sync_all()
+    });
+    let renamed = written.and_then(|()| fs::rename(&temp_path, path));
+    if renamed.is_err() {
+        _ = fs::remove_file(&temp_path);
+    }
+    renamed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::testutil::{build_config, TempDir};
+
+    fn replace(dir: &Path, name: &str, contents: &[u8], flags: &[&str]) -> (usize, Vec<u8>) {
+        let path = dir.join(name);
+        fs::write(&path, contents).unwrap();
+
+        let args = ["minigrep", "--in-place"]
+            .iter()
+            .chain(flags)
+            .map(|a| a.to_string())
+            .chain([path.display().to_string()]);
+        let config = build_config(args).unwrap();
+        let matcher = QueryMatcher::new(&config).unwrap();
+        let replacement = config.replace.as_deref().unwrap();
+
+        let selected = replace_in_place(&config, &matcher, &path, replacement).unwrap();
+        (selected, fs::read(&path).unwrap())
+    }
+
+    #[test]
+    fn rewrites_only_the_matches() {
+        let dir = TempDir::new("replace");
+
+        let contents = b"let x = 1;\r\nlet y = x;\n\xff x\nx";
+        let (selected, edited) = replace(&dir, "a.rs", contents, &["--replace=z", "-w", "x"]);
+        assert_eq!(4, selected);
+        assert_eq!(&b"let z = 1;\r\nlet y = z;\n\xff x\nz"[..], edited);
+
+        let (_, edited) = replace(
+            &dir,
+            "b.txt",
+            b"2024-06-01\n",
+            &[
+                "--backup",
+                "-E",
+                r"(\d+)-(\d+)-(\d+)",
+                "--replace",
+                "$3/$2/$1",
+            ],
+        );
+        assert_eq!(&b"01/06/2024\n"[..], edited);
+        assert_eq!(
+            b"2024-06-01\n"[..],
+            fs::read(dir.join("b.txt.bak")).unwrap()
+        );
+
+        // Binary files are left as they are
+        let (_, edited) = replace(&dir, "c.bin", b"x\0x\n", &["--replace=z", "x"]);
+        assert_eq!(&b"x\0x\n"[..], edited);
+    }
+}
@@ -0,0 +1,297 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
+
+use encoding_rs::Encoding;
+use encoding_rs_io::DecodeReaderBytesBuilder;
+
+use crate::decompress::Compression;
+use crate::literal::{self, Contents};
+use crate::multiline::search_multiline;
+use crate::{search_reader, Config, Context, Match, Matcher, SearchOptions, STDIN_PATH};
+
+/// How much of a stream is looked at up front, the most a `BufReader` reads
+/// in one go; [`Input::peek`] shows as much of a whole file.
+const PEEK_LEN: usize = 8 * 1024;
+
+/// Receives what a [`Searcher`] finds, as soon as it finds it.
+///
+/// Only `matched` has to be written. Each line handler returns whether to
+/// carry on searching, so a sink can stop a search early.
+pub trait Sink {
+    /// Called before anything is read from `path`.
+    fn begin(&mut self, _path: &Path) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// A selected line.
+    fn matched(&mut self, m: &Match) -> io::Result<bool>;
+
+    /// A line of context shown around a selected one.
+    fn context(&mut self, _m: &Match) -> io::Result<bool> {
+        Ok(true)
+    }
+
+    /// Called once `path` has been searched, with how many lines were selected.
+    fn finish(&mut self, _path: &Path, _selected: usize) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// An opened input: either a whole file in memory, or a stream to read
+/// through a buffer at a time.
+pub enum Input {
+    Whole(Contents),
+    Stream(Box<dyn BufRead>),
+}
+
+impl Input {
+    /// The first bytes of the input, without using them up, e.g. to check
+    /// whether it's binary.
+    pub fn peek(&mut self) -> io::Result<&[u8]> {
+        match self {
+            Self::Whole(contents) => Ok(&contents[..contents.len().min(PEEK_LEN)]),
+            Self::Stream(reader) => reader.fill_buf(),
+        }
+    }
+}
+
+/// Reads inputs and hands every selected line, and any context around it,
+/// to a [`Sink`].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Searcher {
+    pub options: SearchOptions,
+    /// Decompress gzip, zstd and bzip2 input (`-z`)
+    pub search_zip: bool,
+    /// Decode input from this encoding rather than reading it as UTF-8
+    pub encoding: Option<&'static Encoding>,
+    /// Let matches span lines, reading each input whole (`-U`)
+    pub multiline: bool,
+}
+
+impl Searcher {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            options: config.search_options(),
+            search_zip: config.search_zip,
+            encoding: config.encoding,
+            multiline: config.multiline,
+        }
+    }
+
+    /// Searches the file at `path`, or standard input for `-`.
+    pub fn search_path<M, S>(&self, matcher: &M, path: &Path, sink: &mut S) -> io::Result<usize>
+    where
+        M: Matcher + ?Sized,
+        S: Sink + ?Sized,
+    {
+        let input = self.open_input(path)?;
+        sink.begin(path)?;
+        let selected = self.search_input(matcher, input, sink)?;
+        sink.finish(path, selected)?;
+        Ok(selected)
+    }
+
+    /// Searches everything `reader` has left, returning how many lines were
+    /// selected. Unlike `search_path` this leaves `begin` and `finish` to
+    /// the caller.
+    pub fn search_reader<M, R, S>(
+        &self,
+        matcher: &M,
+        mut reader: R,
+        sink: &mut S,
+    ) -> io::Result<usize>
+    where
+        M: Matcher + ?Sized,
+        R: BufRead,
+        S: Sink + ?Sized,
+    {
+        if self.multiline {
+            let mut contents = Vec::new();
+            reader.read_to_end(&mut contents)?;
+            return self.search_slice(matcher, &contents, sink);
+        }
+        search_reader(matcher, reader, &self.options, to_sink(sink))
+    }
+
+    /// Searches an input from `open_input`, leaving `begin` and `finish` to
+    /// the caller like `search_reader`.
+    pub fn search_input<M, S>(&self, matcher: &M, input: Input, sink: &mut S) -> io::Result<usize>
+    where
+        M: Matcher + ?Sized,
+        S: Sink + ?Sized,
+    {
+        match input {
+            Input::Whole(contents) => self.search_slice(matcher, &contents, sink),
+            Input::Stream(reader) => self.search_reader(matcher, reader, sink),
+        }
+    }
+
+    /// Searches the whole of an input that's already in memory.
+    ///
+    /// When every match has to contain some literal text, and there are no
+    /// context lines or `-v` that need every line looked at, this finds that
+    /// text across the whole buffer first; see [`literal::search_literal`].
+    pub fn search_slice<M, S>(
+        &self,
+        matcher: &M,
+        haystack: &[u8],
+        sink: &mut S,
+    ) -> io::Result<usize>
+    where
+        M: Matcher + ?Sized,
+        S: Sink + ?Sized,
+    {
+        if self.multiline {
+            return search_multiline(matcher, haystack, &self.options, to_sink(sink));
+        }
+        let every_line = self.options.context != Context::default() || self.options.invert_match;
+        let needle = matcher
+            .literal()
+            .filter(|&needle| literal::is_searchable(needle));
+        match needle {
+            Some(needle) if !every_line => {
+                let max_count = self.options.max_count;
+                literal::search_literal(matcher, needle, haystack, max_count, |m| sink.matched(&m))
+            }
+            _ => self.search_reader(matcher, haystack, sink),
+        }
+    }
+
+    /// Opens `path` the fastest way to search it: a regular file that needs no
+    /// decompressing or decoding is loaded whole, anything else is streamed.
+    pub fn open_input(&self, path: &Path) -> io::Result<Input> {
+        if !self.search_zip && self.encoding.is_none() {
+            if let Some(contents) = Contents::load(path)? {
+                return Ok(Input::Whole(contents));
+            }
+        }
+        self.open(path).map(Input::Stream)
+    }
+
+    /// Opens `path` for searching, or standard input for `-`, seeing through
+    /// compression and decoding it first if asked to.
+    pub fn open(&self, path: &Path) -> io::Result<Box<dyn BufRead>> {
+        let mut reader: Box<dyn BufRead> = if path == Path::new(STDIN_PATH) {
+            Box::new(io::stdin().lock())
+        } else {
+            Box::new(BufReader::new(File::open(path)?))
+        };
+        if self.search_zip {
+            if let Some(compression) = Compression::detect(path, reader.fill_buf()?) {
+                let decoded = compression.decoder(reader).and_then(|mut decoder| {
+                    decoder.fill_buf()?;
+                    Ok(decoder)
+                });
+                // A file that only looks compressed, by its first bytes or its
+                // name, is read as it is if it can be opened again from the start
+                reader = match decoded {
+                    Ok(decoder) => decoder,
+                    Err(_) if path != Path::new(STDIN_PATH) => {
+                        Box::new(BufReader::new(File::open(path)?))
+                    }
+                    Err(e) => return Err(e),
+                };
+            }
+        }
+        if let Some(encoding) = self.encoding {
+            let decoder = DecodeReaderBytesBuilder::new()
+                .encoding(Some(encoding))
+                .build(reader);
+            reader = Box::new(BufReader::new(decoder));
+        }
+        Ok(reader)
+    }
+}
+
+/// Hands each line found to the sink method for its kind.
+fn to_sink<S: Sink + ?Sized>(sink: &mut S) -> impl FnMut(Match) -> io::Result<bool> + '_ {
+    |m| {
+        if m.is_context {
+            sink.context(&m)
+        } else {
+            sink.matched(&m)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes down every event, to check what a search reports.
+    #[derive(Default)]
+    struct Events(Vec<String>);
+
+    impl Sink for Events {
+        fn begin(&mut self, path: &Path) -> io::Result<()> {
+            self.0.push(format!("begin {}", path.display()));
+            Ok(())
+        }
+
+        fn matched(&mut self, m: &Match) -> io::Result<bool> {
+            self.0.push(format!("match {}: {}", m.line_number, m.line));
+            // Stop after the second match
+            Ok(self.0.len() < 3)
+        }
+
+        fn context(&mut self, m: &Match) -> io::Result<bool> {
+            self.0
+                .push(format!("context {}: {}", m.line_number, m.line));
+            Ok(true)
+        }
+
+        fn finish(&mut self, _path: &Path, selected: usize) -> io::Result<()> {
+            self.0.push(format!("finish {selected}"));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn sink_gets_matches_and_context_and_can_stop() {
+        let searcher = Searcher {
+            options: SearchOptions {
+                context: crate::Context {
+                    before: 1,
+                    after: 0,
+                },
+                ..SearchOptions::default()
+            },
+            ..Searcher::default()
+        };
+        let contents = "one\ntwo fish\nthree fish\nfour fish\n";
+
+        let mut events = Events::default();
+        let selected = searcher
+            .search_reader("fish", contents.as_bytes(), &mut events)
+            .unwrap();
+
+        assert_eq!(2, selected);
+        assert_eq!(
+            vec!["context 1: one", "match 2: two fish", "match 3: three fish"],
+            events.0
+        );
+    }
+
+    #[test]
+    fn search_path_reports_begin_and_finish() {
+        let mut events = Events::default();
+        Searcher::default()
+            .search_path(
+                &regex::Regex::new("^To").unwrap(),
+                Path::new("poem.txt"),
+                &mut events,
+            )
+            .unwrap();
+
+        assert_eq!(
+            vec![
+                "begin poem.txt",
+                "match 8: To tell your name the livelong day",
+                "match 9: To an admiring bog!",
+                "finish 2"
+            ],
+            events.0
+        );
+    }
+}
@@ -0,0 +1,141 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::{walk, ArgsError};
+
+/// Defaults read from `config.toml`, for example:
+///
+/// ```toml
+/// # Read before the command line's own flags, which win over these
+/// flags = ["--line-number", "--hidden"]
+/// # Skipped while walking, written like lines of a .gitignore
+/// ignore = ["*.min.js", "vendor/"]
+///
+/// [colors]
+/// match = "1;32"
+/// path = "35"
+/// line_number = "32"
+/// ```
+#[derive(Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Settings {
+    pub flags: Vec<String>,
+    pub ignore: Vec<String>,
+    pub colors: ColorScheme,
+}
+
+/// The ANSI SGR codes used for each part of the output when coloring, like
+/// grep's `GREP_COLORS`. An empty code leaves that part uncolored.
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ColorScheme {
+    #[serde(rename = "match")]
+    pub matched: String,
+    pub path: String,
+    pub line_number: String,
+}
+
+impl Default for ColorScheme {
+    fn default() -> Self {
+        Self {
+            // Bold red, the same as grep's default for matched text
+            matched: String::from("1;31"),
+            path: String::new(),
+            line_number: String::new(),
+        }
+    }
+}
+
+impl Settings {
+    /// Reads the config file named by `MINIGREP_CONFIG`, or else
+    /// `minigrep/config.toml` in the user's config directory.
+    ///
+    /// Having no config file at all is fine, unless `MINIGREP_CONFIG` asked
+    /// for one.
+    pub fn load() -> Result<Self, ArgsError> {
+        let (path, required) = match env::var_os("MINIGREP_CONFIG").filter(|p| !p.is_empty()) {
+            Some(path) => (PathBuf::from(path), true),
+            None => match default_path() {
+                Some(path) => (path, false),
+                None => return Ok(Self::default()),
+            },
+        };
+        let error = |reason: String| ArgsError::ConfigFile {
+            path: path.display().to_string(),
+            reason,
+        };
+
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound && !required => {
+                return Ok(Self::default())
+            }
+            Err(e) => return Err(error(e.to_string())),
+        };
+
+        Self::parse(&contents).map_err(error)
+    }
+
+    fn parse(contents: &str) -> Result<Self, String> {
+        let settings: Self = toml::from_str(contents).map_err(|e| e.message().to_string())?;
+
+        walk::build_ignore(Path::new(""), &settings.ignore).map_err(|e| e.to_string())?;
+        let colors = &settings.colors;
+        for code in [&colors.matched, &colors.path, &colors.line_number] {
+            if !code.chars().all(|c| c.is_ascii_digit() || c == ';') {
+                return Err(format!("'{code}' is not an SGR color code like \"1;31\""));
+            }
+        }
+
+        Ok(settings)
+    }
+}
+
+/// `$XDG_CONFIG_HOME/minigrep/config.toml`, falling back on `~/.config`.
+fn default_path() -> Option<PathBuf> {
+    let config_home = env::var_os("XDG_CONFIG_HOME")
+        .filter(|p| !p.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+
+    Some(config_home.join("minigrep").join("config.toml"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_flags_ignores_and_colors() {
+        let settings = Settings::parse(
+            r#"
+flags = ["-n", "--hidden"]
+ignore = ["*.lock"]
+
+[colors]
+match = "1;32"
+path = "35"
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(vec!["-n", "--hidden"], settings.flags);
+        assert_eq!(vec!["*.lock"], settings.ignore);
+        assert_eq!("1;32", settings.colors.matched);
+        assert_eq!("35", settings.colors.path);
+        assert_eq!("", settings.colors.line_number);
+
+        assert_eq!(Settings::default(), Settings::parse("").unwrap());
+    }
+
+    #[test]
+    fn rejects_unknown_keys_and_bad_colors() {
+        assert!(Settings::parse("flag = [\"-n\"]").is_err());
+        assert!(Settings::parse("[colors]\nmatch = \"red\"").is_err());
+        assert!(Settings::parse("ignore = [\"a{b\"]").is_err());
+    }
+}
@@ -0,0 +1,54 @@
+use std::env;
+use std::fs;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+
+use crate::settings::Settings;
+use crate::{ArgsError, Config};
+
+/// Builds a config from `args`, which start with the program name like
+/// `env::args` does, leaving out any config file on the machine running
+/// the tests.
+pub fn build_config<I>(args: I) -> Result<Config, ArgsError>
+where
+    I: IntoIterator,
+    I::Item: ToString,
+{
+    let mut args = args.into_iter().map(|a| a.to_string());
+    _ = args.next();
+    Config::build_with(Settings::default(), args)
+}
+
+/// An empty directory for one test, named after it, which is removed with
+/// everything in it when dropped, so even a failing test cleans up.
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new(name: &str) -> Self {
+        let path = env::temp_dir().join(format!("minigrep-{name}-{}", std::process::id()));
+        // Left over from a run that was killed
+        _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        Self(path)
+    }
+}
+
+impl Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for TempDir {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        _ = fs::remove_dir_all(&self.0);
+    }
+}
@@ -0,0 +1,438 @@
+use std::error::Error;
+use std::io::{self, IsTerminal, Read, Stderr, Write};
+use std::ops::Range;
+use std::{panic, slice};
+
+use ratatui::backend::CrosstermBackend;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::crossterm::execute;
+use ratatui::crossterm::terminal::{self, EnterAlternateScreen, LeaveAlternateScreen};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, List, ListItem, ListState, Paragraph};
+use ratatui::{Frame, Terminal};
+
+use crate::{
+    collect_files, display_name, search_lines, Config, Matcher, Outcome, QueryMatcher, Searcher,
+};
+
+/// Most lines listed at once, so a short query on big files stays responsive.
+const MAX_HITS: usize = 10_000;
+
+const MATCH_STYLE: Style = Style::new().fg(Color::Red).add_modifier(Modifier::BOLD);
+const DIM_STYLE: Style = Style::new().fg(Color::DarkGray);
+
+/// The search is drawn on stderr, leaving stdout to the chosen `file:line`
+/// so it can be piped or captured; keys are read from the terminal itself.
+type Screen = Terminal<CrosstermBackend<Stderr>>;
+
+/// Opens the interactive search (`--interactive`) over the config's paths.
+///
+/// Results are updated on every keystroke; Enter leaves and prints the
+/// selected line's `file:line`, Esc leaves without printing anything.
+pub fn run(config: &Config) -> Result<Outcome, Box<dyn Error>> {
+    if !io::stderr().is_terminal() {
+        return Err("--interactive needs stderr to be a terminal to draw on".into());
+    }
+    let mut app = App::new(config, load_documents(config));
+    app.query = config.patterns.first().cloned().unwrap_or_default();
+    app.update();
+
+    let mut screen = open_screen().map_err(|e| format!("can't take over the terminal: {e}"))?;
+    let chosen = app.event_loop(&mut screen);
+    close_screen();
+
+    match chosen? {
+        Some(location) => {
+            match writeln!(io::stdout(), "{location}") {
+                // Whatever was meant to read it has gone, nothing left to do
+                Err(e) if e.kind() == io::ErrorKind::BrokenPipe => {}
+                written => written?,
+            }
+            Ok(Outcome::Matched)
+        }
+        None => Ok(Outcome::NoMatch),
+    }
+}
+
+/// Switches the terminal to raw mode and the alternate screen, undoing it
+/// again if that fails halfway or the program panics.
+fn open_screen() -> io::Result<Screen> {
+    terminal::enable_raw_mode()?;
+    let opened = execute!(io::stderr(), EnterAlternateScreen)
+        .and_then(|()| Terminal::new(CrosstermBackend::new(io::stderr())));
+    if opened.is_err() {
+        close_screen();
+        return opened;
+    }
+
+    // So the panic message isn't lost on the alternate screen
+    let hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        close_screen();
+        hook(info);
+    }));
+    opened
+}
+
+/// Leaves the terminal the way it was before `open_screen`, as far as it can.
+fn close_screen() {
+    // Raw mode first, it has more side effects than the alternate screen
+    _ = terminal::disable_raw_mode();
+    _ = execute!(io::stderr(), LeaveAlternateScreen);
+}
+
+/// A file read into memory once, so each new query only has to search it.
+struct Document {
+    name: String,
+    contents: String,
+}
+
+/// Reads every file the config names, reporting the ones that can't be read
+/// and leaving out binary ones, as `run` would.
+fn load_documents(config: &Config) -> Vec<Document> {
+    let searcher = Searcher::new(config);
+    let mut documents = Vec::new();
+
+    for file in collect_files(config) {
+        let loaded = file.and_then(|file| {
+            let mut bytes = Vec::new();
+            searcher.open(&file.path)?.read_to_end(&mut bytes)?;
+            Ok((file, bytes))
+        });
+        match loaded {
+            Ok((_, bytes)) if !config.text && bytes.contains(&0) => {}
+            Ok((file, bytes)) => documents.push(Document {
+                name: display_name(&file.path),
+                contents: String::from_utf8_lossy(&bytes).into_owned(),
+            }),
+            Err(e) => eprintln!("minigrep: {e}"),
+        }
+    }
+
+    documents
+}
+
+/// A matching line in one of the documents.
+struct Hit {
+    document: usize,
+    line_number: usize,
+    line: String,
+    spans: Vec<Range<usize>>,
+}
+
+/// What a key press leads to.
+#[derive(Debug, PartialEq, Eq)]
+enum Step {
+    Continue,
+    Quit,
+    /// Leave, printing this `file:line`
+    Choose(String),
+}
+
+/// The state of the interactive search, kept apart from the terminal so it
+/// can be driven by plain key events.
+struct App<'c> {
+    config: &'c Config,
+    documents: Vec<Document>,
+    query: String,
+    hits: Vec<Hit>,
+    list: ListState,
+    /// Why the current query can't be searched for, e.g. a half-typed regex
+    error: Option<String>,
+}
+
+impl<'c> App<'c> {
+    fn new(config: &'c Config, documents: Vec<Document>) -> Self {
+        Self {
+            config,
+            documents,
+            query: String::new(),
+            hits: Vec::new(),
+            list: ListState::default(),
+            error: None,
+        }
+    }
+
+    fn event_loop(&mut self, terminal: &mut Screen) -> io::Result<Option<String>> {
+        loop {
+            terminal.draw(|frame| self.draw(frame))?;
+            let Event::Key(key) = event::read()? else {
+                continue;
+            };
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            match self.handle_key(key) {
+                Step::Continue => {}
+                Step::Quit => return Ok(None),
+                Step::Choose(location) => return Ok(Some(location)),
+            }
+        }
+    }
+
+    fn handle_key(&mut self, key: KeyEvent) -> Step {
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        match key.code {
+            KeyCode::Esc => return Step::Quit,
+            KeyCode::Char('c') if ctrl => return Step::Quit,
+            KeyCode::Enter => {
+                if let Some(location) = self.selected_location() {
+                    return Step::Choose(location);
+                }
+            }
+            KeyCode::Up => self.move_selection(-1),
+            KeyCode::Down => self.move_selection(1),
+            KeyCode::PageUp => self.move_selection(-10),
+            KeyCode::PageDown => self.move_selection(10),
+            KeyCode::Char('u') if ctrl => {
+                self.query.clear();
+                self.update();
+            }
+            KeyCode::Backspace => {
+                self.query.pop();
+                self.update();
+            }
+            KeyCode::Char(c) if !ctrl => {
+                self.query.push(c);
+                self.update();
+            }
+            _ => {}
+        }
+        Step::Continue
+    }
+
+    /// Searches every document again for the current query.
+    fn update(&mut self) {
+        self.error = None;
+        if self.query.is_empty() {
+            self.hits.clear();
+            self.list.select(None);
+            return;
+        }
+
+        // On a bad pattern keep showing the last good results
+        let patterns = slice::from_ref(&self.query);
+        let matcher = match QueryMatcher::with_patterns(self.config, patterns) {
+            Ok(matcher) => matcher,
+            Err(e) => {
+                self.error = Some(e.to_string());
+                return;
+            }
+        };
+
+        self.hits.clear();
+        'documents: for (i, document) in self.documents.iter().enumerate() {
+            for m in search_lines(&matcher, &document.contents) {
+                if self.hits.len() == MAX_HITS {
+                    break 'documents;
+                }
+                self.hits.push(Hit {
+                    document: i,
+                    line_number: m.line_number,
+                    line: m.line.to_string(),
+                    spans: matcher.find_spans(m.line),
+                });
+            }
+        }
+        self.list.select((!self.hits.is_empty()).then_some(0));
+    }
+
+    fn move_selection(&mut self, by: isize) {
+        let Some(selected) = self.list.selected() else {
+            return;
+        };
+        let last = self.hits.len().saturating_sub(1);
+        self.list
+            .select(Some(selected.saturating_add_signed(by).min(last)));
+    }
+
+    fn selected_hit(&self) -> Option<&Hit> {
+        self.hits.get(self.list.selected()?)
+    }
+
+    fn selected_location(&self) -> Option<String> {
+        let hit = self.selected_hit()?;
+        let name = &self.documents[hit.document].name;
+        Some(format!("{name}:{}", hit.line_number))
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [input, results, preview, status] = Layout::vertical([
+            Constraint::Length(1),
+            Constraint::Percentage(50),
+            Constraint::Fill(1),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+
+        let prompt = Line::from(vec![Span::styled("> ", DIM_STYLE), Span::raw(&self.query)]);
+        frame.render_widget(Paragraph::new(prompt), input);
+        let cursor_x = input.x + 2 + self.query.chars().count() as u16;
+        frame.set_cursor_position((cursor_x.min(input.right().saturating_sub(1)), input.y));
+
+        let items: Vec<ListItem> = self
+            .hits
+            .iter()
+            .map(|hit| {
+                let name = &self.documents[hit.document].name;
+                let mut spans = vec![Span::styled(
+                    format!("{name}:{}: ", hit.line_number),
+                    DIM_STYLE,
+                )];
+                spans.extend(highlighted(&hit.line, &hit.spans));
+                ListItem::new(Line::from(spans))
+            })
+            .collect();
+        let list = List::new(items)
+            .block(Block::bordered().title(" Results "))
+            .highlight_style(Style::new().add_modifier(Modifier::REVERSED));
+        frame.render_stateful_widget(list, results, &mut self.list);
+
+        self.draw_preview(frame, preview);
+
+        let status_line = match &self.error {
+            Some(error) => Line::styled(error.lines().last().unwrap_or(error), Color::Red),
+            None => Line::styled(
+                format!(
+                    "{} lines in {} files · ↑↓ move · Enter prints file:line · Esc quits",
+                    self.hits.len(),
+                    self.documents.len()
+                ),
+                DIM_STYLE,
+            ),
+        };
+        frame.render_widget(Paragraph::new(status_line), status);
+    }
+
+    /// The selected line in the middle of the lines around it.
+    fn draw_preview(&self, frame: &mut Frame, area: Rect) {
+        let Some(hit) = self.selected_hit() else {
+            frame.render_widget(Block::bordered().title(" Preview "), area);
+            return;
+        };
+        let document = &self.documents[hit.document];
+
+        let around = usize::from(area.height.saturating_sub(2) / 2);
+        let first = hit.line_number.saturating_sub(around).max(1);
+        let lines: Vec<Line> = document
+            .contents
+            .lines()
+            .enumerate()
+            .skip(first - 1)
+            .take(usize::from(area.height))
+            .map(|(i, line)| {
+                let number = Span::styled(format!("{:>5} ", i + 1), DIM_STYLE);
+                if i + 1 == hit.line_number {
+                    let mut spans = vec![number];
+                    spans.extend(highlighted(&hit.line, &hit.spans));
+                    Line::from(spans).style(Style::new().add_modifier(Modifier::BOLD))
+                } else {
+                    Line::from(vec![number, Span::raw(line)])
+                }
+            })
+            .collect();
+
+        let block = Block::bordered().title(format!(" {} ", document.name));
+        frame.render_widget(Paragraph::new(lines).block(block), area);
+    }
+}
+
+/// `line` split into spans, with the matched parts standing out.
+fn highlighted<'a>(line: &'a str, matches: &[Range<usize>]) -> Vec<Span<'a>> {
+    let mut spans = Vec::new();
+    let mut printed = 0;
+    for span in matches {
+        spans.push(Span::raw(&line[printed..span.start]));
+        spans.push(Span::styled(&line[span.clone()], MATCH_STYLE));
+        printed = span.end;
+    }
+    spans.push(Span::raw(&line[printed..]));
+    spans
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::testutil::build_config;
+
+    use ratatui::backend::TestBackend;
+
+    fn config() -> Config {
+        let args = ["minigrep", "--interactive", "poem.txt"];
+        build_config(args).unwrap()
+    }
+
+    fn press(app: &mut App, code: KeyCode) -> Step {
+        app.handle_key(KeyEvent::new(code, KeyModifiers::NONE))
+    }
+
+    fn type_text(app: &mut App, text: &str) {
+        for c in text.chars() {
+            press(app, KeyCode::Char(c));
+        }
+    }
+
+    #[test]
+    fn typing_updates_results_and_enter_picks_one() {
+        let config = config();
+        let mut app = App::new(&config, load_documents(&config));
+
+        type_text(&mut app, "bo");
+        assert_eq!(4, app.hits.len());
+        type_text(&mut app, "g");
+        assert_eq!(1, app.hits.len());
+        press(&mut app, KeyCode::Backspace);
+        assert_eq!(4, app.hits.len());
+        app.handle_key(KeyEvent::new(KeyCode::Char('u'), KeyModifiers::CONTROL));
+        assert!(app.hits.is_empty());
+        type_text(&mut app, "To");
+
+        press(&mut app, KeyCode::Down);
+        press(&mut app, KeyCode::Down);
+        // Stays on the last result
+        assert_eq!(Some(1), app.list.selected());
+        assert_eq!(
+            Step::Choose(String::from("poem.txt:9")),
+            press(&mut app, KeyCode::Enter)
+        );
+        assert_eq!(Step::Quit, press(&mut app, KeyCode::Esc));
+    }
+
+    #[test]
+    fn bad_regex_keeps_the_last_results() {
+        let mut config = config();
+        config.regex = true;
+        let mut app = App::new(&config, load_documents(&config));
+
+        type_text(&mut app, "(frog");
+        assert!(app.error.is_some());
+        type_text(&mut app, "|bog)");
+        assert!(app.error.is_none());
+        assert_eq!(2, app.hits.len());
+    }
+
+    #[test]
+    fn draws_results_and_preview() {
+        let config = config();
+        let mut app = App::new(&config, load_documents(&config));
+        type_text(&mut app, "frog");
+
+        let mut terminal = Terminal::new(TestBackend::new(60, 14)).unwrap();
+        terminal.draw(|frame| app.draw(frame)).unwrap();
+        let screen: String = terminal
+            .backend()
+            .buffer()
+            .content()
+            .chunks(60)
+            .map(|row| row.iter().map(|cell| cell.symbol()).collect::<String>() + "\n")
+            .collect();
+
+        assert!(screen.starts_with("> frog"));
+        assert!(screen.contains("poem.txt:7: How public, like a frog"));
+        assert!(screen.contains("    7 How public, like a frog"));
+        assert!(screen.contains("1 lines in 1 files"));
+    }
+}
@@ -0,0 +1,235 @@
+use std::io;
+use std::path::{Path, PathBuf};
+
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use ignore::WalkBuilder;
+
+use crate::{Config, STDIN_PATH};
+
+/// A file to search, and how it was found.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileEntry {
+    pub path: PathBuf,
+    /// Found by walking a directory rather than named on the command line,
+    /// so it is skipped if it turns out to be binary
+    pub walked: bool,
+}
+
+/// Compiles `--include`/`--exclude` globs into one set, matched against file
+/// names like grep does.
+pub fn build_globs(globs: &[String]) -> Result<GlobSet, globset::Error> {
+    let mut builder = GlobSetBuilder::new();
+    for glob in globs {
+        builder.add(Glob::new(glob)?);
+    }
+    builder.build()
+}
+
+/// Compiles the config file's ignore rules, written like the lines of a
+/// `.gitignore` in `root`.
+pub fn build_ignore(root: &Path, rules: &[String]) -> Result<Gitignore, ignore::Error> {
+    let mut builder = GitignoreBuilder::new(root);
+    for rule in rules {
+        builder.add_line(None, rule)?;
+    }
+    builder.build()
+}
+
+/// Expands the given paths into the list of files to search, keeping any
+/// per-path errors so `run` can report them and carry on with the rest.
+///
+/// Directories are walked in name order when searching recursively, leaving
+/// out hidden files and anything in `.gitignore` or `.ignore` files or the
+/// config file's ignore rules unless `--hidden` or `--no-ignore` say otherwise.
+pub fn collect_files(config: &Config) -> Vec<io::Result<FileEntry>> {
+    let mut files = Vec::new();
+
+    for path in &config.file_paths {
+        let path = Path::new(path);
+        if path == Path::new(STDIN_PATH) {
+            files.push(Ok(FileEntry {
+                path: path.to_path_buf(),
+                walked: false,
+            }));
+        } else if !path.is_dir() {
+            if is_selected(config, path) {
+                files.push(Ok(FileEntry {
+                    path: path.to_path_buf(),
+                    walked: false,
+                }));
+            }
+        } else if config.recursive {
+            walk_dir(config, path, &mut files);
+        } else {
+            files.push(Err(path_error(path, "Is a directory")));
+        }
+    }
+
+    files
+}
+
+fn walk_dir(config: &Config, dir: &Path, files: &mut Vec<io::Result<FileEntry>>) {
+    let respect_ignores = !config.no_ignore;
+    let mut builder = WalkBuilder::new(dir);
+    if respect_ignores && !config.ignore.is_empty() {
+        // Already checked when the config file was read
+        let rules = build_ignore(dir, &config.ignore).unwrap_or_else(|_| Gitignore::empty());
+        builder.filter_entry(move |entry| {
+            let is_dir = entry.file_type().is_some_and(|t| t.is_dir());
+            !rules.matched(entry.path(), is_dir).is_ignore()
+        });
+    }
+    let walker = builder
+        .hidden(!config.hidden)
+        .ignore(respect_ignores)
+        .git_ignore(respect_ignores)
+        .git_global(respect_ignores)
+        .git_exclude(respect_ignores)
+        .parents(respect_ignores)
+        // Honour .gitignore files even outside of a git checkout
+        .require_git(false)
+        // Sort so results come out in the same order on every run
+        .sort_by_file_name(|a, b| a.cmp(b))
+        .build();
+
+    for entry in walker {
+        match entry {
+            Ok(entry) => {
+                let is_file = entry.file_type().is_some_and(|t| !t.is_dir());
+                if is_file && is_selected(config, entry.path()) {
+                    files.push(Ok(FileEntry {
+                        path: entry.into_path(),
+                        walked: true,
+                    }));
+                }
+            }
+            Err(e) => files.push(Err(io::Error::other(e.to_string()))),
+        }
+    }
+}
+
+/// Whether a file's name passes `--include` and `--exclude`.
+fn is_selected(config: &Config, path: &Path) -> bool {
+    let Some(name) = path.file_name() else {
+        return true;
+    };
+    let included = config.include.is_empty() || config.include.is_match(name);
+    included && !config.exclude.is_match(name)
+}
+
+fn path_error(path: &Path, err: impl std::fmt::Display) -> io::Error {
+    io::Error::other(format!("{}: {err}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs;
+
+    use crate::testutil::{build_config, TempDir};
+
+    fn walk(dir: &Path, flags: &[&str]) -> Vec<PathBuf> {
+        let args = ["minigrep", "-r"]
+            .iter()
+            .chain(flags)
+            .map(|a| a.to_string())
+            .chain(["x".to_string(), dir.display().to_string()]);
+        let config = build_config(args).unwrap();
+
+        collect_files(&config)
+            .into_iter()
+            .map(|f| f.unwrap().path.strip_prefix(dir).unwrap().to_path_buf())
+            .collect()
+    }
+
+    #[test]
+    fn recursive_walk_is_sorted_and_keeps_errors() {
+        let dir = TempDir::new("walk");
+        fs::create_dir(dir.join("nested")).unwrap();
+        fs::write(dir.join("b.txt"), "b").unwrap();
+        fs::write(dir.join("a.txt"), "a").unwrap();
+        fs::write(dir.join("nested").join("c.txt"), "c").unwrap();
+
+        let expected = ["a.txt", "b.txt", "nested/c.txt"].map(PathBuf::from);
+        assert_eq!(expected[..], walk(&dir, &[]));
+
+        // Without -r a directory is reported rather than searched
+        let args = ["minigrep", "x", dir.to_str().unwrap()].map(String::from);
+        let found = collect_files(&build_config(args).unwrap());
+        assert_eq!(1, found.len());
+        assert!(found[0].is_err());
+    }
+
+    #[test]
+    fn skips_hidden_and_ignored_files_unless_asked() {
+        let dir = TempDir::new("ignore");
+        fs::create_dir(dir.join("target")).unwrap();
+        fs::create_dir(dir.join(".hidden")).unwrap();
+        fs::write(dir.join(".gitignore"), "target/\n*.log\n").unwrap();
+        fs::write(dir.join(".ignore"), "secret.txt\n").unwrap();
+        fs::write(dir.join("main.rs"), "x").unwrap();
+        fs::write(dir.join("notes.txt"), "x").unwrap();
+        fs::write(dir.join("debug.log"), "x").unwrap();
+        fs::write(dir.join("secret.txt"), "x").unwrap();
+        fs::write(dir.join("target").join("out.rs"), "x").unwrap();
+        fs::write(dir.join(".hidden").join("h.rs"), "x").unwrap();
+
+        let paths = |names: &[&str]| names.iter().map(PathBuf::from).collect::<Vec<_>>();
+
+        assert_eq!(paths(&["main.rs", "notes.txt"]), walk(&dir, &[]));
+        assert_eq!(
+            paths(&[
+                ".gitignore",
+                ".hidden/h.rs",
+                ".ignore",
+                "main.rs",
+                "notes.txt"
+            ]),
+            walk(&dir, &["--hidden"])
+        );
+        assert_eq!(
+            paths(&[
+                "debug.log",
+                "main.rs",
+                "notes.txt",
+                "secret.txt",
+                "target/out.rs"
+            ]),
+            walk(&dir, &["--no-ignore"])
+        );
+        assert_eq!(
+            paths(&["main.rs"]),
+            walk(&dir, &["--include=*.rs", "--include", "*.md"])
+        );
+        assert_eq!(
+            paths(&[".hidden/h.rs", "notes.txt"]),
+            walk(
+                &dir,
+                &["--hidden", "--exclude=main.*", "--exclude=.*ignore"]
+            )
+        );
+    }
+
+    #[test]
+    fn config_file_ignore_rules() {
+        let dir = TempDir::new("rules");
+        fs::create_dir(dir.join("vendor")).unwrap();
+        fs::write(dir.join("app.js"), "x").unwrap();
+        fs::write(dir.join("app.min.js"), "x").unwrap();
+        fs::write(dir.join("vendor").join("lib.js"), "x").unwrap();
+
+        let args = ["minigrep", "-r", "x", dir.to_str().unwrap()].map(String::from);
+        let mut config = build_config(args).unwrap();
+        config.ignore = vec![String::from("*.min.js"), String::from("vendor/")];
+        let found: Vec<_> = collect_files(&config)
+            .into_iter()
+            .map(|f| f.unwrap().path.strip_prefix(&dir).unwrap().to_path_buf())
+            .collect();
+        assert_eq!(vec![PathBuf::from("app.js")], found);
+
+        config.no_ignore = true;
+        assert_eq!(3, collect_files(&config).len());
+    }
+}
@@ -0,0 +1,65 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+use std::time::{Duration, Instant};
+
+const FILES: usize = 200;
+const LINES_PER_FILE: usize = 2_000;
+
+// Builds a directory of files where roughly one line in 37 contains "needle"
+fn generate_corpus(dir: &Path) {
+    fs::create_dir_all(dir).expect("Failed to create the corpus directory");
+
+    for file in 0..FILES {
+        let contents: String = (0..LINES_PER_FILE)
+            .map(|line| {
+                let word = if (file + line) % 37 == 0 {
+                    "needle"
+                } else {
+                    "hay"
+                };
+                format!("file {file} line {line}: some {word} in the stack\n")
+            })
+            .collect();
+        fs::write(dir.join(format!("{file:03}.txt")), contents)
+            .expect("Failed to write a corpus file");
+    }
+}
+
+fn run_minigrep(threads: &str, dir: &Path) -> (Output, Duration) {
+    let start = Instant::now();
+    let output = Command::new(env!("CARGO_BIN_EXE_minigrep"))
+        // Whatever config file the machine has would change the output
+        .args(["--no-config", "-rn", "-j", threads, "needle"])
+        .arg(dir)
+        .output()
+        .expect("Failed to run minigrep");
+    (output, start.elapsed())
+}
+
+#[test]
+fn parallel_output_matches_sequential_output() {
+    let dir: PathBuf = env::temp_dir().join(format!("minigrep-corpus-{}", std::process::id()));
+    generate_corpus(&dir);
+
+    let (sequential, sequential_time) = run_minigrep("1", &dir);
+    let (parallel, parallel_time) = run_minigrep("4", &dir);
+    fs::remove_dir_all(&dir).expect("Failed to clean up the corpus");
+
+    // Shown with `cargo test -- --nocapture`
+    println!("-j1: {sequential_time:?}, -j4: {parallel_time:?}");
+
+    assert!(sequential.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&sequential.stdout),
+        String::from_utf8_lossy(&parallel.stdout),
+        "Parallel search printed different results"
+    );
+
+    // Files come out in the order they were walked, whichever thread ran them
+    let stdout = String::from_utf8(parallel.stdout).expect("Output contains invalid UTF-8");
+    let first_lines: Vec<&str> = stdout.lines().take(2).collect();
+    assert!(first_lines[0].ends_with("000.txt:1:file 0 line 0: some needle in the stack"));
+    assert!(first_lines[1].ends_with("000.txt:38:file 0 line 37: some needle in the stack"));
+}
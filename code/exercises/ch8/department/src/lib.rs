@@ -1,4 +1,25 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::Path;
+use std::process;
+
+// The file formats a Company can be stored in, picked by the file's extension
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Json,
+    Toml,
+}
+
+impl Format {
+    // Files ending in .toml are TOML; anything else is JSON
+    fn of(path: &Path) -> Self {
+        match path.extension() {
+            Some(extension) if extension.eq_ignore_ascii_case("toml") => Self::Toml,
+            _ => Self::Json,
+        }
+    }
+}
 
 // Define a Company struct that will store department data
 pub struct Company {
@@ -21,6 +42,50 @@ impl Company {
         }
     }
 
+    // Load a company from a JSON or TOML file, mapping each department to its employees
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let contents = fs::read_to_string(path)?;
+        let data = match Format::of(path) {
+            Format::Json => serde_json::from_str(&contents).map_err(invalid_data)?,
+            Format::Toml => toml::from_str(&contents).map_err(invalid_data)?,
+        };
+        Ok(Self { data })
+    }
+
+    // Save the company to a JSON or TOML file, depending on its extension.
+    // The file is written under a temporary name and then renamed over the old one,
+    // so a crash halfway through never leaves a half-written file behind.
+    // The old file's permissions are kept, if there is one
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        // Sort the departments so the file doesn't change order between saves
+        let sorted: BTreeMap<&String, &Vec<String>> = self.data.iter().collect();
+        let contents = match Format::of(path) {
+            Format::Json => serde_json::to_string_pretty(&sorted).map_err(invalid_data)? + "\n",
+            Format::Toml => toml::to_string(&sorted).map_err(invalid_data)?,
+        };
+
+        let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+        // Named after this process, so two programs saving at once don't share it
+        let temp_path = path.with_file_name(format!(".{}.{}.tmp", file_name, process::id()));
+        let written = File::create(&temp_path).and_then(|mut temp| {
+            temp.write_all(contents.as_bytes())?;
+            match fs::metadata(path) {
+                Ok(original) => temp.set_permissions(original.permissions())?,
+                // Saving for the first time
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e),
+            }
+            // Make sure the data is on disk before the rename makes it the real file
+            temp.sync_all()
+        });
+        let renamed = written.and_then(|()| fs::rename(&temp_path, path));
+        if renamed.is_err() {
+            // Don't leave the temporary file lying around
+            let _ = fs::remove_file(&temp_path);
+        }
+        renamed
+    }
+
     // Add an employee to a department
     pub fn add_employee(&mut self, employee: &str, department: &str) {
         self.data
@@ -171,3 +236,99 @@ impl Company {
         }
     }
 }
+
+// Turn a parse or serialization error into an io::Error, so load and save have one error type
+fn invalid_data(error: impl std::error::Error + Send + Sync + 'static) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempfile::tempdir;
+
+    fn sample() -> Company {
+        let mut company = Company::new();
+        company.add_employee("Sally", "Engineering");
+        company.add_employee("Amir", "Sales");
+        company.add_employee("Bob", "Engineering");
+        company
+    }
+
+    #[test]
+    fn json_round_trip() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("company.json");
+        sample().save(&path).unwrap();
+        assert!(fs::read_to_string(&path).unwrap().starts_with('{'));
+        assert_eq!(
+            sample().list_all(),
+            Company::load(&path).unwrap().list_all()
+        );
+    }
+
+    #[test]
+    fn toml_round_trip() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("company.toml");
+        sample().save(&path).unwrap();
+        assert!(fs::read_to_string(&path)
+            .unwrap()
+            .starts_with("Engineering = "));
+        assert_eq!(
+            sample().list_all(),
+            Company::load(&path).unwrap().list_all()
+        );
+    }
+
+    #[test]
+    fn format_is_picked_by_extension_ignoring_case() {
+        assert_eq!(Format::of(Path::new("company.TOML")), Format::Toml);
+        assert_eq!(Format::of(Path::new("company.toml")), Format::Toml);
+        assert_eq!(Format::of(Path::new("company.json")), Format::Json);
+        assert_eq!(Format::of(Path::new("company")), Format::Json);
+
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("COMPANY.TOML");
+        sample().save(&path).unwrap();
+        assert!(toml::from_str::<toml::Table>(&fs::read_to_string(&path).unwrap()).is_ok());
+    }
+
+    #[test]
+    fn missing_file_is_not_found() {
+        let dir = tempdir().unwrap();
+        let error = Company::load(&dir.path().join("company.json"))
+            .err()
+            .unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn save_leaves_no_temporary_file() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("company.json");
+        sample().save(&path).unwrap();
+        // Saving again replaces the file rather than failing because it exists
+        sample().save(&path).unwrap();
+        let names: Vec<_> = fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(names, ["company.json"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn save_keeps_the_file_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("company.json");
+        sample().save(&path).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o600)).unwrap();
+        sample().save(&path).unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+}
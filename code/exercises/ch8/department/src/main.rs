@@ -1,25 +1,70 @@
 use department::Company;
+use std::env;
 use std::io::{self, Write};
+use std::path::PathBuf;
+use std::process;
 
 fn main() {
-    let mut company = Company::new();
+    // The only argument is an optional `--db <path>` to keep the roster in
+    let db_path = parse_args(env::args().skip(1)).unwrap_or_else(|message| {
+        eprintln!("{}", message);
+        eprintln!("Usage: department [--db <path>]");
+        process::exit(2);
+    });
+
+    // Start from the saved roster, or an empty one if there isn't one yet
+    let mut company = match &db_path {
+        Some(path) => match Company::load(path) {
+            Ok(company) => company,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Company::new(),
+            Err(e) => {
+                eprintln!("Failed to load {}: {}", path.display(), e);
+                process::exit(1);
+            }
+        },
+        None => Company::new(),
+    };
 
     loop {
         print!("Please enter a command (type 'exit' to quit): ");
         io::stdout().flush().unwrap(); // Flush stdout to make sure the prompt is shown before read_line
 
         let mut input = String::new();
-        io::stdin()
+        let read = io::stdin()
             .read_line(&mut input)
             .expect("Failed to read input");
 
         let input = input.trim();
 
-        if input == "exit" {
+        // Treat the end of input (e.g. Ctrl-D) like exit
+        if input == "exit" || read == 0 {
             break;
         }
 
         let output = company.process_input(input);
         println!("{}", output);
     }
+
+    // Save the roster on the way out, if there's somewhere to save it
+    if let Some(path) = &db_path {
+        if let Err(e) = company.save(path) {
+            eprintln!("Failed to save {}: {}", path.display(), e);
+            process::exit(1);
+        }
+    }
+}
+
+// Read the command line arguments, returning the database path if one was given
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Option<PathBuf>, String> {
+    let mut db_path = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--db" => match args.next() {
+                Some(path) => db_path = Some(PathBuf::from(path)),
+                None => return Err(String::from("--db needs a path")),
+            },
+            _ => return Err(format!("Unexpected argument: {}", arg)),
+        }
+    }
+    Ok(db_path)
 }